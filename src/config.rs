@@ -0,0 +1,102 @@
+/*
+ * Copyright (c) 2022, Sayan Nandan <nandansayan@outlook.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use {
+    crate::{
+        editor::EditorConfig,
+        hooks::Hooks,
+        root::Root,
+        target::{BenchConfig, CrateTarget},
+        Error, Result,
+    },
+    serde::Deserialize,
+    std::{
+        collections::BTreeMap,
+        fs,
+        io::ErrorKind,
+        path::{Path, PathBuf},
+    },
+};
+
+/// The name of the configuration file
+pub const CONFIG_FILE: &str = "dab.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// The project configuration, as read from `dab.toml`
+pub struct Config {
+    /// user-defined presets for `dab init`, mapping the preset name to a layout file (relative
+    /// to `dab.toml`)
+    #[serde(default)]
+    pub presets: BTreeMap<String, String>,
+    /// run `rustfmt` on files patched by `dab`
+    #[serde(default)]
+    pub rustfmt: bool,
+    /// commands to run around module creation
+    #[serde(default)]
+    pub hooks: Hooks,
+    /// open newly created modules in the editor
+    #[serde(default)]
+    pub edit: bool,
+    /// the editor to use
+    #[serde(default)]
+    pub editor: EditorConfig,
+    /// the crate to create modules in, for packages with both a library and a binary
+    #[serde(default)]
+    pub default_target: Option<CrateTarget>,
+    /// the benchmark framework for `dab bench`
+    #[serde(default)]
+    pub bench: BenchConfig,
+    /// the directory of the `dab.toml` that was loaded, which paths in it are relative to
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+impl Config {
+    /// Load the configuration for `root`. This is the `dab.toml` next to the nearest manifest, or
+    /// the one at the workspace root
+    pub fn discover(root: &Root) -> Result<Self> {
+        match &root.workspace_dir {
+            Some(workspace_dir) if !root.dir.join(CONFIG_FILE).is_file() => {
+                Self::load(workspace_dir)
+            }
+            _ => Self::load(&root.dir),
+        }
+    }
+    /// Load the configuration from `dir`, returning the default configuration if the directory
+    /// doesn't have a `dab.toml`
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self> {
+        let path = dir.as_ref().join(CONFIG_FILE);
+        let config = match fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Self::default(),
+            Err(e) => {
+                return Err(Error::Other(format!(
+                    "Couldn't read `{}`: {e}",
+                    path.display()
+                )))
+            }
+        };
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+            ..config
+        })
+    }
+    /// Returns the layout file of the user-defined preset `name`, if any
+    pub fn preset(&self, name: &str) -> Option<PathBuf> {
+        self.presets.get(name).map(|layout| self.dir.join(layout))
+    }
+}
@@ -0,0 +1,94 @@
+/*
+ * Copyright (c) 2022, Sayan Nandan <nandansayan@outlook.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use {
+    crate::{Error, Result},
+    serde::Deserialize,
+    std::{env, path::Path, process::Command},
+};
+
+/// The default arguments to jump to a line (understood by `vi`, `vim`, `nano`, `emacs` and others)
+const DEFAULT_GOTO: &str = "+{line} {file}";
+
+#[derive(Debug, Default, Clone, Deserialize)]
+/// The editor configuration (the `[editor]` table in `dab.toml`)
+pub struct EditorConfig {
+    /// the editor command. `$VISUAL` or `$EDITOR` if not set
+    pub command: Option<String>,
+    /// the arguments to open `{file}` at `{line}`, for example `--goto {file}:{line}` for VS Code
+    pub goto: Option<String>,
+}
+
+impl EditorConfig {
+    /// Open `file` at `line` in the user's editor, waiting for it to exit
+    pub fn open(&self, file: &Path, line: usize) -> Result<()> {
+        let editor = self
+            .command
+            .clone()
+            .or_else(|| env::var("VISUAL").ok())
+            .or_else(|| env::var("EDITOR").ok())
+            .filter(|editor| !editor.trim().is_empty())
+            .ok_or_else(|| Error::Other("no editor set. Set `$VISUAL` or `$EDITOR`".to_owned()))?;
+        let mut editor = editor.split_whitespace();
+        let mut cmd = Command::new(editor.next().unwrap());
+        cmd.args(editor);
+        cmd.args(goto_args(
+            self.goto.as_deref().unwrap_or(DEFAULT_GOTO),
+            &file.to_string_lossy(),
+            line,
+        ));
+        let status = cmd
+            .status()
+            .map_err(|e| Error::Other(format!("failed to launch editor: {e}")))?;
+        if status.success() {
+            Ok(())
+        } else {
+            Error::other(format!("editor exited with {status}"))
+        }
+    }
+}
+
+/// Expand the `goto` arguments for `file` and `line`. The file is appended if `goto` doesn't
+/// mention it
+fn goto_args(goto: &str, file: &str, line: usize) -> Vec<String> {
+    let mut args: Vec<String> = goto
+        .split_whitespace()
+        .map(|arg| {
+            arg.replace("{file}", file)
+                .replace("{line}", &line.to_string())
+        })
+        .collect();
+    if !goto.contains("{file}") {
+        args.push(file.to_owned());
+    }
+    args
+}
+
+#[test]
+fn goto_args_test() {
+    assert_eq!(
+        goto_args(DEFAULT_GOTO, "src/a/mod.rs", 3),
+        ["+3", "src/a/mod.rs"]
+    );
+    assert_eq!(
+        goto_args("--goto {file}:{line}", "src/a.rs", 1),
+        ["--goto", "src/a.rs:1"]
+    );
+    assert_eq!(
+        goto_args("-l {line}", "src/a.rs", 7),
+        ["-l", "7", "src/a.rs"]
+    );
+}
@@ -23,6 +23,7 @@ use std::{
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 /// Errors arising from running `dab`
 pub enum Error {
     /// The module path has empty elements
@@ -35,6 +36,10 @@ pub enum Error {
     IoError(IoError),
     /// The module name was illegal
     BadModuleName,
+    /// The manifest has neither a package nor a workspace
+    EmptyManifest,
+    /// Error from parsing a `dab` TOML file (such as a layout file)
+    TomlError(toml::de::Error),
 }
 
 impl Error {
@@ -56,6 +61,13 @@ impl Display for Error {
             Error::CargoTomlError(cargo) => write!(f, "failed to read `Cargo.toml`: {}", cargo),
             Error::IoError(ioe) => write!(f, "I/O error: {ioe}"),
             Error::BadModuleName => write!(f, "bad module name"),
+            Error::EmptyManifest => {
+                write!(
+                    f,
+                    "`Cargo.toml` has neither a `[package]` nor a `[workspace]`"
+                )
+            }
+            Error::TomlError(toml) => write!(f, "failed to parse TOML: {toml}"),
         }
     }
 }
@@ -73,3 +85,9 @@ impl From<IoError> for Error {
         Self::IoError(e)
     }
 }
+
+impl From<toml::de::Error> for Error {
+    fn from(e: toml::de::Error) -> Self {
+        Self::TomlError(e)
+    }
+}
@@ -0,0 +1,158 @@
+/*
+ * Copyright (c) 2022, Sayan Nandan <nandansayan@outlook.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use {
+    crate::{Error, Result},
+    cargo_toml::{OptionalFile, Package},
+    std::{
+        fs,
+        io::ErrorKind,
+        path::{Path, PathBuf},
+    },
+};
+
+/// The default build script
+const BUILD_RS: &str = "build.rs";
+/// The build script of a new package
+const BUILD_TEMPLATE: &str = "fn main() {\n}\n";
+
+/// Returns the name of the file (in `OUT_DIR`, without the extension) that has the code of the
+/// module at `path_segments`. Nested modules get the names of their parents, so that
+/// `engine::schema` and `schema` don't share a file
+pub fn file_name(path_segments: &[&str]) -> String {
+    path_segments.join("_")
+}
+
+/// The contents of the module that includes the generated `<file_name>.rs`
+pub fn module_contents(file_name: &str) -> String {
+    format!("include!(concat!(env!(\"OUT_DIR\"), \"/{file_name}.rs\"));\n")
+}
+
+/// A pending change to a package's build script that generates the code for a module
+pub struct BuildScript {
+    path: PathBuf,
+    /// the current contents (`None` if the script doesn't exist)
+    current: Option<String>,
+    patched: String,
+}
+
+impl BuildScript {
+    /// Prepare the build script of `package` (in the current directory) to write `<name>.rs` (for
+    /// the module `module`) to `OUT_DIR`. Returns `None` if the script already writes it
+    pub fn prepare(package: &Package, name: &str, module: &str) -> Result<Option<Self>> {
+        let path = match &package.build {
+            Some(OptionalFile::Flag(false)) => {
+                return Error::other(format!(
+                    "package `{}` has `build = false`, so it can't have a build script",
+                    package.name
+                ))
+            }
+            Some(OptionalFile::Path(path)) => path.clone(),
+            _ => PathBuf::from(BUILD_RS),
+        };
+        let current = match fs::read_to_string(&path) {
+            Ok(contents) => Some(contents),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let contents = current.as_deref().unwrap_or(BUILD_TEMPLATE);
+        if contents.contains(&format!("/{name}.rs\"")) {
+            return Ok(None);
+        }
+        let patched = patch_main(contents, name, module).ok_or_else(|| {
+            Error::Other(format!(
+                "couldn't find the `main` function of `{}`",
+                path.display()
+            ))
+        })?;
+        Ok(Some(Self {
+            path,
+            current,
+            patched,
+        }))
+    }
+    /// Returns the path to the build script
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// Write the build script
+    pub fn write(&self) -> Result<()> {
+        Ok(fs::write(&self.path, &self.patched)?)
+    }
+    /// Tell the user about the (written) build script
+    pub fn report(&self) {
+        match self.current {
+            Some(_) => println!("Updated `{}`", self.path.display()),
+            None => println!("Created `{}`", self.path.display()),
+        }
+    }
+    /// Undo [`BuildScript::write`]
+    pub fn revert(&self) -> Result<()> {
+        match &self.current {
+            Some(contents) => fs::write(&self.path, contents)?,
+            None => fs::remove_file(&self.path)?,
+        }
+        Ok(())
+    }
+}
+
+/// Add the code that writes `<name>.rs` (for the module `module`) to `OUT_DIR` at the start of
+/// the `main` function in `contents`. Returns `None` if there's no (multi-line) `main`
+fn patch_main(contents: &str, name: &str, module: &str) -> Option<String> {
+    let mut lines: Vec<&str> = contents.lines().collect();
+    let main = lines.iter().position(|line| {
+        let line = line.trim();
+        // the header can have a return type (`fn main() -> Result<..> {`)
+        line.starts_with("fn main(") && line.ends_with('{')
+    })?;
+    let indent = " ".repeat(lines[main].len() - lines[main].trim_start().len() + 4);
+    let generate = format!(
+        "{indent}// generate `{name}.rs` (included by the `{module}` module)\n\
+        {indent}let out_dir = std::env::var(\"OUT_DIR\").unwrap();\n\
+        {indent}std::fs::write(format!(\"{{out_dir}}/{name}.rs\"), \"\").unwrap();"
+    );
+    lines.insert(main + 1, &generate);
+    let mut patched = lines.join("\n");
+    if contents.ends_with('\n') {
+        patched.push('\n');
+    }
+    Some(patched)
+}
+
+#[test]
+fn patch_main_test() {
+    assert_eq!(
+        patch_main(BUILD_TEMPLATE, "schema", "schema").unwrap(),
+        r#"fn main() {
+    // generate `schema.rs` (included by the `schema` module)
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    std::fs::write(format!("{out_dir}/schema.rs"), "").unwrap();
+}
+"#
+    );
+    let script = "fn main() {\n    println!(\"cargo::rerun-if-changed=build.rs\");\n}";
+    let patched = patch_main(script, "engine_schema", "engine::schema").unwrap();
+    assert!(patched.starts_with(
+        "fn main() {\n    // generate `engine_schema.rs` (included by the `engine::schema` module)"
+    ));
+    assert!(patched.ends_with("unwrap();\n    println!(\"cargo::rerun-if-changed=build.rs\");\n}"));
+    let script = "fn main() -> Result<(), Box<dyn Error>> {\n    Ok(())\n}\n";
+    let patched = patch_main(script, "schema", "schema").unwrap();
+    assert!(patched
+        .starts_with("fn main() -> Result<(), Box<dyn Error>> {\n    // generate `schema.rs`"));
+    assert!(patched.ends_with("unwrap();\n    Ok(())\n}\n"));
+    assert_eq!(patch_main("fn main() {}\n", "schema", "schema"), None);
+}
@@ -0,0 +1,119 @@
+/*
+ * Copyright (c) 2022, Sayan Nandan <nandansayan@outlook.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use {
+    crate::{Error, Result},
+    serde::Deserialize,
+    std::{
+        env,
+        path::{Path, PathBuf},
+        process::Command,
+    },
+};
+
+#[derive(Debug, Default, Clone, Deserialize)]
+/// Commands to run around module creation (the `[hooks]` table in `dab.toml`)
+pub struct Hooks {
+    /// run before any file is created. a failure aborts the operation
+    pub pre_create: Option<String>,
+    /// run once the module has been created
+    pub post_create: Option<String>,
+}
+
+/// The context passed to a hook through environment variables
+pub struct HookContext<'a> {
+    /// the name of the package (`DAB_PACKAGE`)
+    pub package: &'a str,
+    /// the path to the module (`DAB_MODULE_PATH`)
+    pub module_path: &'a str,
+    /// the files created for the module (`DAB_CREATED_FILES`, separated like `PATH`)
+    pub created_files: &'a [PathBuf],
+    /// the parent file that receives the module entry (`DAB_PARENT_FILE`)
+    pub parent_file: &'a Path,
+}
+
+impl Hooks {
+    /// Run the `pre_create` hook, if any
+    pub fn pre_create(&self, ctx: &HookContext) -> Result<()> {
+        match &self.pre_create {
+            Some(cmd) => run_hook("pre_create", cmd, ctx),
+            None => Ok(()),
+        }
+    }
+    /// Run the `post_create` hook, if any
+    pub fn post_create(&self, ctx: &HookContext) -> Result<()> {
+        match &self.post_create {
+            Some(cmd) => run_hook("post_create", cmd, ctx),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Run the hook command `cmd` using the system shell, in the package directory
+fn run_hook(hook: &str, cmd: &str, ctx: &HookContext) -> Result<()> {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C");
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c");
+        command
+    };
+    let created_files = env::join_paths(ctx.created_files)
+        .map_err(|e| Error::Other(format!("bad path for `{hook}` hook: {e}")))?;
+    let status = command
+        .arg(cmd)
+        .env("DAB_PACKAGE", ctx.package)
+        .env("DAB_MODULE_PATH", ctx.module_path)
+        .env("DAB_CREATED_FILES", created_files)
+        .env("DAB_PARENT_FILE", ctx.parent_file)
+        .status()
+        .map_err(|e| Error::Other(format!("failed to run `{hook}` hook: {e}")))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Error::other(format!("`{hook}` hook `{cmd}` failed ({status})"))
+    }
+}
+
+#[test]
+fn failing_pre_create_test() {
+    use {
+        crate::{module::ModuleOptions, package},
+        cargo_toml::Package,
+        std::fs,
+    };
+    let options = ModuleOptions {
+        hooks: Hooks {
+            pre_create: Some("exit 1".to_owned()),
+            post_create: Some("touch src/post_create".to_owned()),
+        },
+        no_mod_folder: Some(false),
+        ..Default::default()
+    };
+    let package = Package::new("dab", "0.2.0");
+    let e = package::create_module_in_package("hooked", options, package, Path::new(""))
+        .unwrap_err()
+        .to_string();
+    assert!(e.contains("`pre_create` hook `exit 1` failed"), "{e}");
+    // the tree is left untouched
+    assert!(!Path::new("src/hooked").exists());
+    assert!(!Path::new("src/post_create").exists());
+    assert!(!fs::read_to_string("src/main.rs")
+        .unwrap()
+        .contains("mod hooked;"));
+}
@@ -0,0 +1,394 @@
+/*
+ * Copyright (c) 2022, Sayan Nandan <nandansayan@outlook.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use {
+    crate::{
+        module::{self, ModuleOptions, Visibility},
+        target::{self, CrateTarget},
+        utils,
+        workspace::Members,
+        Error, Result,
+    },
+    cargo_toml::{Manifest, Package},
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::{BTreeMap, HashSet},
+        env, fs,
+        path::{Path, PathBuf},
+    },
+};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+/// A layout file describing the desired module tree of a package or workspace. In a workspace,
+/// the first segment of every module path is the workspace member
+pub struct Layout {
+    #[serde(default, rename = "module")]
+    pub modules: Vec<LayoutModule>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// A single module in a layout file
+pub struct LayoutModule {
+    /// the full path to the module (`a::b::c`)
+    pub path: String,
+    /// the visibility of the module (`pub`, `pub(crate)`, ...). private if omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<String>,
+    /// the `cfg` predicate for the module entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cfg: Option<String>,
+    /// mark the module entry with `#[macro_use]`
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub macro_use: bool,
+    /// create `<module>.rs` instead of `<module>/mod.rs` (or the other way around if false). If
+    /// omitted, the style is picked from the package's edition
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_mod_folder: Option<bool>,
+    /// path to a template file (relative to the layout file) used for the module's contents
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+impl Layout {
+    /// Load a layout file
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| Error::Other(format!("Couldn't read layout `{}`: {e}", path.display())))?;
+        Ok(toml::from_str(&contents)?)
+    }
+    /// Resolve the modules in this layout into module paths and their options, using `base` for
+    /// anything the layout doesn't specify. Templates are read relative to `base_dir`
+    pub fn into_modules(
+        self,
+        base: &ModuleOptions,
+        base_dir: &Path,
+    ) -> Result<Vec<(String, ModuleOptions)>> {
+        self.modules
+            .into_iter()
+            .map(|module| {
+                let mut options = base.clone();
+                if let Some(vis) = &module.visibility {
+                    options.visibility = Visibility::parse(vis)?;
+                }
+                options.cfg = module.cfg;
+                options.macro_use = module.macro_use;
+                options.no_mod_folder = module.no_mod_folder.or(options.no_mod_folder);
+                if let Some(template) = module.template {
+                    let template = base_dir.join(template);
+                    options.contents = Some(fs::read_to_string(&template).map_err(|e| {
+                        Error::Other(format!(
+                            "Couldn't read template `{}`: {e}",
+                            template.display()
+                        ))
+                    })?);
+                }
+                Ok((module.path, options))
+            })
+            .collect()
+    }
+}
+
+/// Apply the layout file at `layout_path` to the package or workspace described by `manifest`
+pub fn apply_file(layout_path: &str, base: ModuleOptions, manifest: Manifest) -> Result<()> {
+    let base_dir = Path::new(layout_path)
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let modules = Layout::from_path(layout_path)?.into_modules(&base, &base_dir)?;
+    apply(modules, base.target.as_ref(), manifest)
+}
+
+/// Create every missing module in `modules` (in the `target` crate of each package), leaving
+/// existing modules alone. Any drift between the modules and the source tree is reported
+pub fn apply(
+    modules: Vec<(String, ModuleOptions)>,
+    target: Option<&CrateTarget>,
+    manifest: Manifest,
+) -> Result<()> {
+    let members = match &manifest.workspace {
+        Some(workspace) => Some(Members::load(&env::current_dir()?, workspace)?),
+        None => None,
+    };
+    let root_package = match (manifest.package, &members) {
+        (None, None) => return Err(Error::EmptyManifest),
+        (root_package, _) => root_package,
+    };
+    // validate the paths and group them by the package they belong to (`None` for the root
+    // package)
+    let mut packages: BTreeMap<Option<String>, Vec<(Vec<&str>, &ModuleOptions)>> = BTreeMap::new();
+    for (path, options) in modules.iter() {
+        let mut segments: Vec<&str> = path.split("::").collect();
+        if segments.iter().any(|s| s.is_empty()) {
+            return Err(Error::EmptyPath);
+        }
+        let root_name = root_package.as_ref().map(|p| p.name.as_str());
+        let member = members
+            .as_ref()
+            .and_then(|members| members.qualifier(&segments, root_name));
+        let package = match member {
+            Some(member) => {
+                segments.remove(0);
+                Some(member.to_owned())
+            }
+            None if root_package.is_some() => None,
+            None => return Error::other(format!("`{path}` doesn't name a workspace member")),
+        };
+        packages
+            .entry(package)
+            .or_default()
+            .push((segments, options));
+    }
+    let mut drift = Vec::new();
+    for (member, modules) in packages {
+        match (member, &root_package, &members) {
+            (Some(member), _, Some(members)) => {
+                let prefix = format!("{member}::");
+                members.in_member(&member, |package| {
+                    apply_in_package(&package, target, modules, &prefix, &mut drift)
+                })?;
+            }
+            (None, Some(package), _) => apply_in_package(package, target, modules, "", &mut drift)?,
+            _ => unreachable!("paths are always grouped by a known package"),
+        }
+    }
+    if drift.is_empty() {
+        println!("Layout is in sync");
+    } else {
+        println!("Drift:");
+        for entry in drift {
+            println!("  - {entry}");
+        }
+    }
+    Ok(())
+}
+
+/// Apply the given modules to the package `package` in the current directory
+fn apply_in_package(
+    package: &Package,
+    target: Option<&CrateTarget>,
+    mut modules: Vec<(Vec<&str>, &ModuleOptions)>,
+    prefix: &str,
+    drift: &mut Vec<String>,
+) -> Result<()> {
+    let (mut root, mut moving) = target::module_root(&target::root_file(package, target)?)?;
+    let expected: HashSet<String> = modules.iter().map(|(s, _)| s.join("::")).collect();
+    // parents have to be created before their children. since new entries are added to the top
+    // of the parent, we create siblings in reverse so that they end up in the layout's order
+    let mut indexed: Vec<_> = modules.drain(..).enumerate().collect();
+    indexed.sort_by(|(ia, (a, _)), (ib, (b, _))| a.len().cmp(&b.len()).then(ib.cmp(ia)));
+    for (_, (segments, options)) in indexed {
+        let (name, parent_segments) = segments.split_last().unwrap();
+        let path = format!("{prefix}{}", segments.join("::"));
+        let parent = module::resolve_parent(&root, parent_segments)?;
+        let decl = parent
+            .declarations()?
+            .into_iter()
+            .find(|decl| decl.name == *name);
+        let file = module::find_module_file(&parent.dir, name)?;
+        match (decl, file) {
+            (Some(decl), Some(file)) => {
+                let no_mod_folder = !file.ends_with(utils::MOD_RS);
+                let wanted = options.no_mod_folder_in(package.edition());
+                if no_mod_folder != wanted {
+                    drift.push(format!(
+                        "`{path}` is `{}` but the layout wants {}",
+                        file.display(),
+                        if wanted {
+                            format!("`{name}.rs`")
+                        } else {
+                            format!("`{name}/{}`", utils::MOD_RS)
+                        }
+                    ));
+                }
+                if decl.visibility != options.visibility {
+                    drift.push(format!(
+                        "`{path}` is {} but the layout wants {}",
+                        decl.visibility, options.visibility
+                    ));
+                }
+                if decl.macro_use != options.macro_use {
+                    drift.push(format!(
+                        "`{path}` {} `#[macro_use]` but the layout {}",
+                        if decl.macro_use {
+                            "has"
+                        } else {
+                            "doesn't have"
+                        },
+                        if options.macro_use {
+                            "wants it"
+                        } else {
+                            "doesn't"
+                        }
+                    ));
+                }
+                if decl.cfg != options.cfg {
+                    drift.push(format!(
+                        "`{path}` has cfg `{}` but the layout wants `{}`",
+                        decl.cfg.as_deref().unwrap_or("none"),
+                        options.cfg.as_deref().unwrap_or("none")
+                    ));
+                }
+            }
+            (Some(_), None) => drift.push(format!("`{path}` is declared but has no file")),
+            (None, Some(file)) => drift.push(format!(
+                "`{path}` has a file (`{}`) but isn't declared",
+                file.display()
+            )),
+            (None, None) => {
+                let mut module = module::plan_module(package, &root, &segments, options)?;
+                // a binary is moved for the first module that's created for it, and moved back
+                // if that module can't be created
+                let moving = moving.take();
+                if let Some(bin) = &moving {
+                    bin.prepare(&mut module);
+                }
+                options.hooks.pre_create(&module.hook_context(package))?;
+                match moving {
+                    Some(mut bin) => {
+                        bin.apply()?;
+                        root = bin.root();
+                        if let Err(e) = module.create(package, options.clone()) {
+                            if let Err(e) = bin.revert() {
+                                eprintln!("warning: failed to move the binary back: {e}");
+                            }
+                            return Err(e);
+                        }
+                    }
+                    None => {
+                        module.create(package, options.clone())?;
+                    }
+                }
+                println!("Created `{path}`");
+            }
+        }
+    }
+    for module in export_package(package, &root.file.to_string_lossy(), prefix)? {
+        let path = &module.path[prefix.len()..];
+        if !expected.contains(path) {
+            drift.push(format!("`{}` isn't in the layout", module.path));
+        }
+    }
+    Ok(())
+}
+
+/// Export the layout of the package or workspace described by `manifest` (using the `target`
+/// crate of each package). This writes to `out` or to the standard output if no file is provided
+pub fn export(out: Option<&str>, target: Option<&CrateTarget>, manifest: Manifest) -> Result<()> {
+    let mut layout = Layout::default();
+    if let Some(package) = &manifest.package {
+        layout.modules = export_package(package, &target::root_file(package, target)?, "")?;
+    }
+    let root_package = manifest.package.map(|package| package.name);
+    match manifest.workspace {
+        Some(workspace) => {
+            let members = Members::load(&env::current_dir()?, &workspace)?;
+            // the root package (if any) has already been exported
+            for member in members
+                .names()
+                .filter(|member| Some(*member) != root_package.as_deref())
+            {
+                let prefix = format!("{member}::");
+                let modules = members.in_member(member, |package| {
+                    export_package(&package, &target::root_file(&package, target)?, &prefix)
+                })?;
+                layout.modules.extend(modules);
+            }
+        }
+        None if root_package.is_none() => return Err(Error::EmptyManifest),
+        None => {}
+    }
+    let layout = toml::to_string(&layout)
+        .map_err(|e| Error::Other(format!("Failed to serialize layout: {e}")))?;
+    match out {
+        Some(out) => fs::write(out, layout)?,
+        None => print!("{layout}"),
+    }
+    Ok(())
+}
+
+/// Export the layout of the crate with the root file `root_file` in the package `package` (in the
+/// current directory), prefixing every path with `prefix`
+fn export_package(package: &Package, root_file: &str, prefix: &str) -> Result<Vec<LayoutModule>> {
+    let mut modules = Vec::new();
+    // only the modules that don't use the edition's style need `no-mod-folder`
+    let default = ModuleOptions::default().no_mod_folder_in(package.edition());
+    walk(Path::new(root_file), true, prefix, default, &mut modules)?;
+    Ok(modules)
+}
+
+/// Walk the module tree starting at `file`, in declaration order. `default` is the module style
+/// of the package (see [`ModuleOptions::no_mod_folder_in`])
+fn walk(
+    file: &Path,
+    is_root: bool,
+    prefix: &str,
+    default: bool,
+    out: &mut Vec<LayoutModule>,
+) -> Result<()> {
+    let dir = module::child_dir(file, is_root);
+    for decl in module::scan_declarations(&fs::read_to_string(file)?) {
+        let path = format!("{prefix}{}", decl.name);
+        let child: Option<PathBuf> = module::find_module_file(&dir, &decl.name)?;
+        out.push(LayoutModule {
+            path: path.clone(),
+            visibility: (!decl.visibility.is_private()).then(|| decl.visibility.to_string()),
+            cfg: decl.cfg,
+            macro_use: decl.macro_use,
+            no_mod_folder: child
+                .as_ref()
+                .map(|f| !f.ends_with(utils::MOD_RS))
+                .filter(|&no_mod_folder| no_mod_folder != default),
+            template: None,
+        });
+        if let Some(child) = child {
+            walk(&child, false, &format!("{path}::"), default, out)?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn layout_roundtrip() {
+    const LAYOUT: &str = r#"[[module]]
+path = "errors"
+visibility = "pub(crate)"
+
+[[module]]
+path = "macros"
+macro-use = true
+
+[[module]]
+path = "sys"
+cfg = "unix"
+no-mod-folder = true
+
+[[module]]
+path = "sys::unix"
+template = "templates/unix.rs"
+"#;
+    let layout: Layout = toml::from_str(LAYOUT).unwrap();
+    assert_eq!(layout.modules.len(), 4);
+    assert_eq!(layout.modules[0].visibility.as_deref(), Some("pub(crate)"));
+    assert!(layout.modules[1].macro_use);
+    assert_eq!(layout.modules[2].no_mod_folder, Some(true));
+    assert_eq!(toml::to_string(&layout).unwrap(), LAYOUT);
+}
@@ -17,10 +17,20 @@
 mod workspace;
 #[macro_use]
 mod macros;
+mod config;
+mod editor;
 mod errors;
+mod generated;
+mod hooks;
+mod layout;
+mod manifest;
 mod module;
 mod package;
+mod preset;
+mod root;
 mod runner;
+mod target;
+mod transaction;
 mod utils;
 
 use {
@@ -0,0 +1,240 @@
+/*
+ * Copyright (c) 2022, Sayan Nandan <nandansayan@outlook.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use {
+    crate::{utils, Error, Result},
+    std::{io::Write, path::Path},
+    toml_edit::{Array, ArrayOfTables, DocumentMut, Item, Key, Table, TableLike, Value},
+};
+
+/// Edit the manifest at `path` in place, preserving its formatting. The edit is done COW-style
+/// so that the manifest is left untouched if anything fails
+pub fn edit(path: &Path, f: impl FnOnce(&mut DocumentMut) -> Result<()>) -> Result<()> {
+    utils::cowfile(&path.to_string_lossy(), None, |file, contents| {
+        let mut doc = parse(path, contents)?;
+        f(&mut doc)?;
+        file.write_all(doc.to_string().as_bytes())?;
+        Ok(())
+    })
+}
+
+/// Parse the manifest at `path` (with the given contents) for editing
+pub fn parse(path: &Path, contents: &str) -> Result<DocumentMut> {
+    contents
+        .parse()
+        .map_err(|e| Error::Other(format!("failed to parse `{}`: {e}", path.display())))
+}
+
+/// Returns the table at `key`, creating it if it doesn't exist
+pub fn table_mut<'a>(parent: &'a mut Table, key: &str) -> Result<&'a mut Table> {
+    parent
+        .entry(key)
+        .or_insert_with(|| Item::Table(Table::new()))
+        .as_table_mut()
+        .ok_or_else(|| Error::Other(format!("`{key}` isn't a table")))
+}
+
+/// Append `table` to the array of tables at `key` (like `[[example]]`), creating it if needed
+pub fn push_table(doc: &mut DocumentMut, key: &str, table: Table) -> Result<()> {
+    doc.entry(key)
+        .or_insert_with(|| Item::ArrayOfTables(ArrayOfTables::new()))
+        .as_array_of_tables_mut()
+        .ok_or_else(|| Error::Other(format!("`{key}` isn't an array of tables")))?
+        .push(table);
+    Ok(())
+}
+
+/// The tables that dependencies are listed in
+const DEPENDENCY_TABLES: [&str; 3] = ["dependencies", "dev-dependencies", "build-dependencies"];
+
+/// Returns every dependency table of the manifest, including the platform-specific ones (but not
+/// `[workspace.dependencies]`)
+pub fn dependency_tables_mut(doc: &mut DocumentMut) -> Vec<&mut dyn TableLike> {
+    let mut tables = Vec::new();
+    for (key, item) in doc.as_table_mut().iter_mut() {
+        if DEPENDENCY_TABLES.contains(&key.get()) {
+            tables.extend(item.as_table_like_mut());
+        } else if key.get() == "target" {
+            let targets = item
+                .as_table_like_mut()
+                .into_iter()
+                .flat_map(|t| t.iter_mut());
+            for (_, target) in targets {
+                let target = target
+                    .as_table_like_mut()
+                    .into_iter()
+                    .flat_map(|t| t.iter_mut());
+                for (key, item) in target {
+                    if DEPENDENCY_TABLES.contains(&key.get()) {
+                        tables.extend(item.as_table_like_mut());
+                    }
+                }
+            }
+        }
+    }
+    tables
+}
+
+/// Returns the keys of the dependencies in `table` on the local package `name`, i.e. the ones
+/// that have a `path` or are inherited from the workspace
+pub fn local_dependency_keys(table: &dyn TableLike, name: &str) -> Vec<String> {
+    table
+        .iter()
+        .filter(|(key, item)| {
+            let package = item.get("package").and_then(Item::as_str).unwrap_or(key);
+            let inherited = item.get("workspace").and_then(Item::as_bool) == Some(true);
+            package == name && (item.get("path").is_some() || inherited)
+        })
+        .map(|(key, _)| key.to_owned())
+        .collect()
+}
+
+/// Rename the key `old` in `table` to `new`, keeping its position and formatting
+pub fn rename_key(table: &mut dyn TableLike, old: &str, new: &str) {
+    let entries: Vec<(Key, Item)> = table
+        .iter()
+        .filter_map(|(key, _)| table.get_key_value(key))
+        .map(|(key, item)| (key.clone(), item.clone()))
+        .collect();
+    table.clear();
+    for (key, item) in entries {
+        let key = if key.get() == old {
+            Key::new(new).with_leaf_decor(key.leaf_decor().clone())
+        } else {
+            key
+        };
+        table.entry_format(&key).or_insert(item);
+    }
+}
+
+/// Set the string at `key` in `table`, keeping the formatting of the current value if any
+pub fn set_str(table: &mut dyn TableLike, key: &str, value: &str) {
+    match table.get_mut(key).and_then(Item::as_value_mut) {
+        Some(current) => replace_str(current, value),
+        None => {
+            table.insert(key, toml_edit::value(value));
+        }
+    }
+}
+
+/// Replace `current` with the string `value`, keeping its formatting
+pub fn replace_str(current: &mut Value, value: &str) {
+    let decor = current.decor().clone();
+    *current = value.into();
+    *current.decor_mut() = decor;
+}
+
+/// Append `value` to `array`, using the same formatting as the last element (so that multi-line
+/// arrays stay multi-line). A comment after the last element stays with that element
+pub fn push_formatted(array: &mut Array, value: impl Into<Value>) {
+    let mut value = value.into();
+    if let Some(last) = array.iter().last() {
+        let decor = last.decor();
+        let prefix = decor.prefix().and_then(|p| p.as_str()).unwrap_or(" ");
+        // only keep the indentation from the prefix, since it might have comments
+        let prefix = match prefix.rfind('\n') {
+            Some(lf) => &prefix[lf..],
+            // the first element of an inline array usually has no space before it
+            None if prefix.is_empty() && array.len() == 1 => " ",
+            None => prefix,
+        };
+        let trailing = array.trailing().as_str().unwrap_or("").to_owned();
+        value = value.decorated(format!("{}{prefix}", trailing.trim_end_matches('\n')), "");
+        array.set_trailing(if trailing.ends_with('\n') { "\n" } else { "" });
+    }
+    array.push_formatted(value);
+}
+
+#[test]
+fn dependency_edit_test() {
+    const MANIFEST: &str = r#"[package]
+name = "skyd"
+
+[dependencies]
+serde = "1"
+libsky = { path = "../libsky" } # the library
+sky = { package = "libsky", workspace = true }
+
+[target.'cfg(unix)'.dev-dependencies]
+libsky = { version = "1", path = "../libsky" }
+"#;
+    let mut doc: DocumentMut = MANIFEST.parse().unwrap();
+    let mut tables = dependency_tables_mut(&mut doc);
+    assert_eq!(tables.len(), 2);
+    for table in tables.iter_mut() {
+        for key in local_dependency_keys(&**table, "libsky") {
+            let dependency = table.get_mut(&key).unwrap().as_table_like_mut().unwrap();
+            if dependency.contains_key("path") {
+                set_str(dependency, "path", "../skylib");
+            }
+            if key == "libsky" {
+                rename_key(&mut **table, "libsky", "skylib");
+            } else {
+                set_str(dependency, "package", "skylib");
+            }
+        }
+    }
+    assert_eq!(
+        doc.to_string(),
+        r#"[package]
+name = "skyd"
+
+[dependencies]
+serde = "1"
+skylib = { path = "../skylib" } # the library
+sky = { package = "skylib", workspace = true }
+
+[target.'cfg(unix)'.dev-dependencies]
+skylib = { version = "1", path = "../skylib" }
+"#
+    );
+}
+
+#[test]
+fn push_formatted_test() {
+    const MANIFEST: &str = r#"[workspace]
+# our crates
+members = [
+    "skyd",
+    "libsky", # the library
+]
+"#;
+    let mut doc: DocumentMut = MANIFEST.parse().unwrap();
+    let members = doc["workspace"]["members"].as_array_mut().unwrap();
+    push_formatted(members, "crates/skyhash");
+    // the comment is now in the prefix of the element we just added
+    let mut doc: DocumentMut = doc.to_string().parse().unwrap();
+    let members = doc["workspace"]["members"].as_array_mut().unwrap();
+    push_formatted(members, "tools/gen");
+    let mut inline: DocumentMut = "members = [\"a\", \"b\"]".parse().unwrap();
+    push_formatted(inline["members"].as_array_mut().unwrap(), "c");
+    assert_eq!(inline.to_string(), "members = [\"a\", \"b\", \"c\"]\n");
+    let mut single: DocumentMut = "members = [\"a\"]".parse().unwrap();
+    push_formatted(single["members"].as_array_mut().unwrap(), "b");
+    assert_eq!(single.to_string(), "members = [\"a\", \"b\"]\n");
+    assert_eq!(
+        doc.to_string(),
+        r#"[workspace]
+# our crates
+members = [
+    "skyd",
+    "libsky", # the library
+    "crates/skyhash",
+    "tools/gen",
+]
+"#
+    );
+}
@@ -16,94 +16,753 @@
 
 use {
     crate::{
+        hooks::{HookContext, Hooks},
+        target::CrateTarget,
         utils::{self, add_mod_rs},
         Error, Result,
     },
-    std::{collections::HashSet, fs, io::Write},
+    cargo_toml::{Edition, Package},
+    std::{
+        collections::HashSet,
+        env,
+        fmt::{Display, Formatter, Result as FmtResult},
+        fs,
+        io::Write,
+        path::{Path, PathBuf},
+    },
 };
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+/// The visibility of a module declaration
+pub enum Visibility {
+    /// `mod <module>`
+    #[default]
+    Private,
+    /// `pub mod <module>`
+    Public,
+    /// `pub(<restriction>) mod <module>`, for example `pub(crate)`
+    Restricted(String),
+}
+
+impl Visibility {
+    /// Parse the visibility from its source form (`pub`, `pub(crate)`, ...). An empty string or
+    /// `private` is a private module
+    pub fn parse(vis: &str) -> Result<Self> {
+        let vis = vis.trim();
+        match vis {
+            "" | "private" => Ok(Self::Private),
+            "pub" => Ok(Self::Public),
+            _ => match vis
+                .strip_prefix("pub(")
+                .and_then(|restriction| restriction.strip_suffix(')'))
+            {
+                Some(restriction) if !restriction.trim().is_empty() => {
+                    Ok(Self::Restricted(restriction.trim().to_owned()))
+                }
+                _ => Error::other(format!("bad visibility `{vis}`")),
+            },
+        }
+    }
+    /// Returns true if this is a private module
+    pub fn is_private(&self) -> bool {
+        matches!(self, Self::Private)
+    }
+    /// The prefix to use for a declaration (includes the trailing space, if any)
+    fn decl_prefix(&self) -> String {
+        match self {
+            Self::Private => String::new(),
+            Self::Public => "pub ".to_owned(),
+            Self::Restricted(restriction) => format!("pub({restriction}) "),
+        }
+    }
+}
+
+impl Display for Visibility {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Private => write!(f, "private"),
+            Self::Public => write!(f, "pub"),
+            Self::Restricted(restriction) => write!(f, "pub({restriction})"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
 /// The configuration to use while creating a module
 pub struct ModuleOptions {
     /// show the help menu
     pub is_help: bool,
-    /// module should be public
-    pub is_public: bool,
+    /// open the new module in the editor
+    pub is_edit: bool,
+    /// the crate to create the module in, or the library package to create (`new-member`)
+    pub target: Option<CrateTarget>,
+    /// the visibility of the module entry
+    pub visibility: Visibility,
     /// module entry (`mod <module>`) should be appended at license header end
     pub from_comment_header_bottom: bool,
-    /// module should be create as `<module>.rs` instead of `<module>/mod.rs`
-    pub no_mod_folder: bool,
+    /// module should be created as `<module>.rs` instead of `<module>/mod.rs`. If not set, the
+    /// style is picked from the edition (see [`ModuleOptions::no_mod_folder_in`])
+    pub no_mod_folder: Option<bool>,
+    /// the `cfg` predicate to put on the module entry (`#[cfg(<predicate>)]`)
+    pub cfg: Option<String>,
+    /// module entry should be marked with `#[macro_use]`
+    pub macro_use: bool,
+    /// initial contents of the module file (empty if none)
+    pub contents: Option<String>,
+    /// hooks to run around module creation
+    pub hooks: Hooks,
+    /// run `rustfmt` on the patched parent file
+    pub rustfmt: bool,
+    /// share the `common` module with the new test (`test`)
+    pub common: bool,
+    /// re-export the new proc-macro crate from the package it's for (`proc-macro`)
+    pub reexport: bool,
+    /// the module includes code generated by the build script (`generated`)
+    pub generated: bool,
 }
 
 impl ModuleOptions {
-    /// The flag count (inclusive of short and long)
-    const FLAG_COUNT: usize = 7;
+    /// The flags that `dab` knows about (both the long and the short forms)
+    const FLAGS: [&'static str; 17] = [
+        "help",
+        "public",
+        "P",
+        "cskip",
+        "C",
+        "dskip",
+        "D",
+        "mod-folder",
+        "M",
+        "fmt",
+        "F",
+        "edit",
+        "E",
+        "lib",
+        "common",
+        "reexport",
+        "force",
+    ];
     /// Check the options from the given hashset
     pub fn process_options(&mut self, flags: &HashSet<&str>) -> Result<()> {
-        self.is_public = flags.contains("public") || flags.contains("P"); // 2
-        self.is_help = flags.contains("help"); // 1
-        self.from_comment_header_bottom = flags.contains("cskip") || flags.contains("C"); // 2
-        self.no_mod_folder = flags.contains("dskip") || flags.contains("D"); // 2
-        if flags.len() > Self::FLAG_COUNT {
-            return Error::other("Unknown flags");
+        if let Some(flag) = flags.iter().find(|flag| !Self::FLAGS.contains(flag)) {
+            let dashes = if flag.len() == 1 { "-" } else { "--" };
+            return Error::other(format!(
+                "Unknown flag `{dashes}{flag}`. Run `--help` for usage"
+            ));
+        }
+        if flags.contains("public") || flags.contains("P") {
+            self.visibility = Visibility::Public;
         }
+        self.is_help = flags.contains("help");
+        self.from_comment_header_bottom = flags.contains("cskip") || flags.contains("C");
+        let dskip = flags.contains("dskip") || flags.contains("D");
+        let mod_folder = flags.contains("mod-folder") || flags.contains("M");
+        self.no_mod_folder = match (dskip, mod_folder) {
+            (true, true) => {
+                return Error::other("`--dskip` and `--mod-folder` can't be used together")
+            }
+            (true, false) => Some(true),
+            (false, true) => Some(false),
+            (false, false) => None,
+        };
+        self.rustfmt = flags.contains("fmt") || flags.contains("F");
+        self.is_edit = flags.contains("edit") || flags.contains("E");
+        if flags.contains("lib") {
+            self.target = Some(CrateTarget::Lib);
+        }
+        self.common = flags.contains("common");
+        self.reexport = flags.contains("reexport");
         Ok(())
     }
+    /// Returns true if modules are created as `<module>.rs` in a package of the given edition.
+    /// Unless a style was picked, that's the case from the 2018 edition, while 2015 crates get
+    /// `<module>/mod.rs`
+    pub fn no_mod_folder_in(&self, edition: Edition) -> bool {
+        self.no_mod_folder.unwrap_or(edition >= Edition::E2018)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A module declaration (`mod <module>;`) found in a source file
+pub struct ModDecl {
+    /// the name of the module
+    pub name: String,
+    /// the visibility of the declaration
+    pub visibility: Visibility,
+    /// the `cfg` predicate on the declaration, if any
+    pub cfg: Option<String>,
+    /// the declaration is marked with `#[macro_use]`
+    pub macro_use: bool,
+}
+
+/// Scan the top-level module declarations (`mod <module>;`) in the given source. Declarations
+/// in inline modules and anything else nested in braces are ignored
+pub fn scan_declarations(contents: &str) -> Vec<ModDecl> {
+    scan(contents)
+        .decls
+        .into_iter()
+        .filter(|(scope, _)| scope.is_empty())
+        .map(|(_, decl)| decl)
+        .collect()
+}
+
+#[derive(Debug, Default)]
+/// The modules in a source file, as found by [`scan`]
+struct Scan {
+    /// the module declarations, along with the path to the inline module they're in (empty for
+    /// the declarations of the file itself)
+    decls: Vec<(Vec<String>, ModDecl)>,
+    /// the inline modules
+    blocks: Vec<InlineBlock>,
+}
+
+#[derive(Debug)]
+/// An inline module (`mod <module> { .. }`) in a source file
+struct InlineBlock {
+    /// the path to the module within the file
+    path: Vec<String>,
+    /// the line that opens the block
+    open: usize,
+    /// the line that closes the block (the same as `open` for single-line blocks)
+    close: usize,
+}
+
+/// Scan the module declarations and inline modules in the given source, line by line. Anything
+/// nested in braces (other than inline modules) is ignored
+fn scan(contents: &str) -> Scan {
+    let mut scan = Scan::default();
+    let mut depth = 0usize;
+    // the inline modules we're in: the depth of their items and their block
+    let mut stack: Vec<(usize, usize)> = Vec::new();
+    let mut in_block_comment = false;
+    let mut cfg = None;
+    let mut macro_use = false;
+    for (idx, line) in contents.lines().enumerate() {
+        let mut line = line.trim();
+        if in_block_comment {
+            match line.find("*/") {
+                Some(end) => {
+                    in_block_comment = false;
+                    line = line[end + 2..].trim();
+                }
+                None => continue,
+            }
+        }
+        if line.starts_with("/*") && !line.contains("*/") {
+            in_block_comment = true;
+            continue;
+        }
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        let code = line.split("//").next().unwrap_or_default().trim();
+        if depth == stack.last().map_or(0, |(items, _)| *items) {
+            let scope = || match stack.last() {
+                Some((_, block)) => scan.blocks[*block].path.clone(),
+                None => Vec::new(),
+            };
+            if let Some(attr) = line.strip_prefix("#[").and_then(|l| l.strip_suffix(']')) {
+                if let Some(predicate) = attr.strip_prefix("cfg(").and_then(|a| a.strip_suffix(')'))
+                {
+                    cfg = Some(predicate.trim().to_owned());
+                }
+                macro_use |= attr.trim() == "macro_use";
+                continue;
+            }
+            if let Some(decl) = parse_declaration(line) {
+                scan.decls.push((
+                    scope(),
+                    ModDecl {
+                        cfg: cfg.take(),
+                        macro_use: std::mem::take(&mut macro_use),
+                        ..decl
+                    },
+                ));
+                continue;
+            }
+            if let Some((name, items)) = parse_inline_module(code) {
+                let mut path = scope();
+                path.push(name.to_owned());
+                match items {
+                    Some(items) => {
+                        for item in items.split(';') {
+                            if let Some(decl) = parse_declaration(&format!("{};", item.trim())) {
+                                scan.decls.push((path.clone(), decl));
+                            }
+                        }
+                    }
+                    None => {
+                        depth += 1;
+                        stack.push((depth, scan.blocks.len()));
+                    }
+                }
+                scan.blocks.push(InlineBlock {
+                    path,
+                    open: idx,
+                    close: idx,
+                });
+                cfg = None;
+                macro_use = false;
+                continue;
+            }
+        }
+        cfg = None;
+        macro_use = false;
+        let opening = code.matches('{').count();
+        let closing = code.matches('}').count();
+        depth = (depth + opening).saturating_sub(closing);
+        while let Some(&(items, block)) = stack.last() {
+            if depth >= items {
+                break;
+            }
+            scan.blocks[block].close = idx;
+            stack.pop();
+        }
+    }
+    scan
+}
+
+/// Parse the (code of a) line as the start of an inline module (`mod <module> {`). Returns the
+/// name of the module, along with its items if the block is closed on the same line
+fn parse_inline_module(code: &str) -> Option<(&str, Option<&str>)> {
+    let (_, rest) = strip_visibility(code)?;
+    let rest = rest.strip_prefix("mod ")?.trim_start();
+    let (name, block) = rest.split_at(rest.find(|c: char| c.is_whitespace() || c == '{')?);
+    utils::validate_module_name(name).ok()?;
+    let block = block.trim().strip_prefix('{')?.trim();
+    if block.is_empty() {
+        return Some((name, None));
+    }
+    let items = block.strip_suffix('}')?;
+    // a single-line block can't have anything nested in braces
+    (!items.contains(['{', '}'])).then_some((name, Some(items)))
+}
+
+/// Split the visibility off the start of an item
+fn strip_visibility(line: &str) -> Option<(Visibility, &str)> {
+    if let Some(rest) = line.strip_prefix("pub(") {
+        let end = rest.find(')')?;
+        Some((
+            Visibility::Restricted(rest[..end].trim().to_owned()),
+            rest[end + 1..].trim_start(),
+        ))
+    } else if let Some(rest) = line.strip_prefix("pub ") {
+        Some((Visibility::Public, rest.trim_start()))
+    } else {
+        Some((Visibility::Private, line))
+    }
+}
+
+/// Parse a single line as a module declaration
+fn parse_declaration(line: &str) -> Option<ModDecl> {
+    let (visibility, rest) = strip_visibility(line)?;
+    let name = rest.strip_prefix("mod ")?.trim().strip_suffix(';')?.trim();
+    utils::validate_module_name(name).ok()?;
+    Some(ModDecl {
+        name: name.to_owned(),
+        visibility,
+        cfg: None,
+        macro_use: false,
+    })
+}
+
+/// Returns the directory in which the child modules of the module at `file` live. For the
+/// root file and `mod.rs` files, this is the directory of the file itself, while for any other
+/// `<module>.rs` file this is the `<module>/` directory next to it
+pub fn child_dir(file: &Path, is_root: bool) -> PathBuf {
+    let parent = file.parent().map(Path::to_path_buf).unwrap_or_default();
+    if is_root || file.file_name().is_some_and(|name| name == utils::MOD_RS) {
+        parent
+    } else {
+        file.with_extension("")
+    }
+}
+
+/// Returns the file for the module `name` whose parent module lives in `parent_dir`, if any
+pub fn find_module_file(parent_dir: &Path, name: &str) -> Result<Option<PathBuf>> {
+    let flat = parent_dir.join(format!("{name}.rs"));
+    let folder = add_mod_rs(parent_dir.join(name));
+    match (flat.is_file(), folder.is_file()) {
+        (true, true) => Error::other(format!(
+            "module `{name}` is ambiguous as both `{}` and `{}` exist",
+            flat.display(),
+            folder.display()
+        )),
+        (true, false) => Ok(Some(flat)),
+        (false, true) => Ok(Some(folder)),
+        (false, false) => Ok(None),
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The parent of a module: a file, or an inline module (`mod <module> { .. }`) in a file
+pub struct Parent {
+    /// the file with the parent's declarations
+    pub file: PathBuf,
+    /// the path to the inline module within `file` (empty if the parent is the file itself)
+    pub inline: Vec<String>,
+    /// the directory in which the child modules live
+    pub dir: PathBuf,
+}
+
+impl Parent {
+    /// The parent of the top-level modules of the crate with the root file `root_file`
+    pub fn root(root_file: &Path) -> Self {
+        Self {
+            file: root_file.to_path_buf(),
+            inline: Vec::new(),
+            dir: child_dir(root_file, true),
+        }
+    }
+    /// Returns the module declarations of the parent
+    pub fn declarations(&self) -> Result<Vec<ModDecl>> {
+        Ok(scan(&fs::read_to_string(&self.file)?)
+            .decls
+            .into_iter()
+            .filter(|(scope, _)| *scope == self.inline)
+            .map(|(_, decl)| decl)
+            .collect())
+    }
+    /// Returns true if the parent has the inline module `name`
+    fn has_inline(&self, scan: &Scan, name: &str) -> bool {
+        scan.blocks.iter().any(|block| {
+            block.path.split_last() == Some((&name.to_owned(), self.inline.as_slice()))
+        })
+    }
+}
+
+/// Resolve the parent for the module at `path_segments` (the module itself) starting from the
+/// crate root `root` (see [`Parent::root`]). An empty path resolves to the root itself
+pub fn resolve_parent(root: &Parent, path_segments: &[&str]) -> Result<Parent> {
+    let mut parent = root.clone();
+    for (idx, segment) in path_segments.iter().enumerate() {
+        let scan = scan(&fs::read_to_string(&parent.file)?);
+        let declared = scan
+            .decls
+            .iter()
+            .any(|(scope, decl)| *scope == parent.inline && decl.name == *segment);
+        match find_module_file(&parent.dir, segment)? {
+            Some(file) if declared => {
+                parent = Parent {
+                    dir: child_dir(&file, false),
+                    file,
+                    inline: Vec::new(),
+                }
+            }
+            // the children of inline modules live in a directory named after the module
+            _ if parent.has_inline(&scan, segment) => {
+                parent.inline.push(segment.to_string());
+                parent.dir.push(segment);
+            }
+            _ => {
+                return Error::other(format!(
+                    "module `{}` doesn't exist",
+                    path_segments[..=idx].join("::")
+                ))
+            }
+        }
+    }
+    Ok(parent)
 }
 
-/// Create the module using the provided `root_file_path`, path segments and the module options
-pub fn create_module(
-    root_file_path: &str,
+#[derive(Debug)]
+/// A newly created module
+pub struct CreatedModule {
+    /// the module file
+    pub file: PathBuf,
+    /// the first line after any template contents
+    pub first_line: usize,
+}
+
+#[derive(Debug)]
+/// A module that can be created: its path has been checked and its file picked, but nothing
+/// has been written yet
+pub struct PlannedModule {
+    /// the path to the module (`a::b`)
+    path: String,
+    /// the name of the module
+    name: String,
+    /// the parent that gets the module entry
+    parent: Parent,
+    /// the files created along with the module, starting with the module file
+    files: Vec<PathBuf>,
+}
+
+/// Plan the module at `path_segments` under the crate root `root`, with the module options in
+/// the package `package`
+pub fn plan_module(
+    package: &Package,
+    root: &Parent,
     path_segments: &[&str],
-    options: ModuleOptions,
-) -> Result<()> {
+    options: &ModuleOptions,
+) -> Result<PlannedModule> {
     if path_segments
         .iter()
         .any(|segment| utils::validate_module_name(segment).is_err())
     {
         return Error::bad_module_name();
     }
-    if path_segments.len() != 1 {
-        // TODO(@ohsayan): Support nested modules
-        return Error::other(
-            "modules other than the root aren't supported yet. this will be implemented in a future version"
-            .to_string()
-        );
+    let (module_name, parent_segments) = match path_segments.split_last() {
+        Some(split) => split,
+        None => return Err(Error::EmptyPath),
+    };
+    utils::check_keyword(module_name, package.edition())?;
+    let parent = resolve_parent(root, parent_segments)?;
+    let parent_dir = &parent.dir;
+    if parent
+        .declarations()?
+        .iter()
+        .any(|decl| decl.name == *module_name)
+        || parent.has_inline(&scan(&fs::read_to_string(&parent.file)?), module_name)
+        || find_module_file(parent_dir, module_name)?.is_some()
+    {
+        return Error::other(format!(
+            "module `{}` already exists",
+            path_segments.join("::")
+        ));
     }
-
-    // create the module directory (src/<mod>/)
-    if options.no_mod_folder {
-        // just create <module>.rs
-        let filepath = format!("src/{}.rs", path_segments[0]);
-        fs::File::create(filepath)?;
+    // the module directory (<parent>/<mod>/)
+    let filepath = if options.no_mod_folder_in(package.edition()) {
+        // just <module>.rs
+        parent_dir.join(format!("{module_name}.rs"))
     } else {
         // this is wrt the package root
-        let dirpath = "src/".to_owned() + path_segments[0];
-        let filepath = add_mod_rs(&dirpath);
-        fs::create_dir(&dirpath)?;
-        // create the module file
-        fs::File::create(filepath)?;
+        add_mod_rs(parent_dir.join(module_name))
+    };
+    Ok(PlannedModule {
+        path: path_segments.join("::"),
+        name: module_name.to_string(),
+        parent,
+        files: vec![filepath],
+    })
+}
+
+impl PlannedModule {
+    /// Add a file that the caller creates along with the module, for the hooks
+    pub fn also_creates(&mut self, file: PathBuf) {
+        self.files.push(file);
+    }
+    /// Point the module at the parent file `to`, if its parent file was moved there from `from`
+    pub fn parent_moved(&mut self, from: &Path, to: &Path) {
+        if self.parent.file == from {
+            self.parent.file = to.to_path_buf();
+        }
+    }
+    /// Returns the context that the hooks are run with
+    pub fn hook_context<'a>(&'a self, package: &'a Package) -> HookContext<'a> {
+        HookContext {
+            package: &package.name,
+            module_path: &self.path,
+            created_files: &self.files,
+            parent_file: &self.parent.file,
+        }
+    }
+    /// Create the module, once the `pre_create` hook has allowed it
+    pub fn create(self, package: &Package, options: ModuleOptions) -> Result<CreatedModule> {
+        let filepath = &self.files[0];
+        // only the directories that don't exist yet are ours to remove in a rollback
+        let mut created_dirs: Vec<&Path> = filepath
+            .parent()
+            .unwrap_or(&self.parent.dir)
+            .ancestors()
+            .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+            .collect();
+        created_dirs.reverse();
+        let mut created_file = false;
+        let created: Result<()> = (|| {
+            for dir in &created_dirs {
+                fs::create_dir(dir)?;
+            }
+            // create the module file
+            let mut module_file = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(filepath)?;
+            created_file = true;
+            if let Some(contents) = &options.contents {
+                module_file.write_all(contents.as_bytes())?;
+            }
+            // append the module entry to the top of the parent file (or inline module)
+            let parent = &self.parent;
+            declare_in(package, &parent.file, &parent.inline, &self.name, &options)
+        })();
+        if created.is_err() {
+            // roll back the module we just created
+            if created_file {
+                let _ = fs::remove_file(filepath);
+            }
+            for dir in created_dirs.iter().rev() {
+                let _ = fs::remove_dir(dir);
+            }
+        }
+        created?;
+        options.hooks.post_create(&self.hook_context(package))?;
+        let first_line = match &options.contents {
+            Some(contents) if contents.ends_with('\n') => contents.lines().count() + 1,
+            Some(contents) => contents.lines().count().max(1),
+            None => 1,
+        };
+        Ok(CreatedModule {
+            // the module might be in another package than the one we'll be in
+            file: env::current_dir()?.join(filepath),
+            first_line,
+        })
     }
+}
 
-    // append the module entry to the top of the main.rs file
-    utils::cowfile(root_file_path, |file, contents| {
-        patch_file(path_segments[0], contents, options, file)
-    })?;
-    Ok(())
+/// Declare the module `name` at the top of `parent_file` (in the package `package`), unless it's
+/// already declared there
+pub fn declare_module(
+    package: &Package,
+    parent_file: &Path,
+    name: &str,
+    options: &ModuleOptions,
+) -> Result<()> {
+    declare_in(package, parent_file, &[], name, options)
+}
+
+/// Declare the module `name` at the top of the inline module at `inline` in `parent_file` (or at
+/// the top of the file if `inline` is empty), unless it's already declared there
+fn declare_in(
+    package: &Package,
+    parent_file: &Path,
+    inline: &[String],
+    name: &str,
+    options: &ModuleOptions,
+) -> Result<()> {
+    if scan(&fs::read_to_string(parent_file)?)
+        .decls
+        .iter()
+        .any(|(scope, decl)| scope == inline && decl.name == name)
+    {
+        return Ok(());
+    }
+    let rustfmt = options.rustfmt.then(|| package.edition());
+    utils::cowfile(&parent_file.to_string_lossy(), rustfmt, |file, contents| {
+        if inline.is_empty() {
+            patch_file(name, contents, options, file)
+        } else {
+            patch_inline(inline, name, contents, options, file)
+        }
+    })
+}
+
+/// Returns the declaration for the module (including any attributes), without a trailing LF
+fn declaration(final_module_name: &str, options: &ModuleOptions) -> String {
+    let mut decl = String::new();
+    if let Some(cfg) = &options.cfg {
+        decl.push_str(&format!("#[cfg({cfg})]\n"));
+    }
+    if options.macro_use {
+        decl.push_str("#[macro_use]\n");
+    }
+    decl.push_str(&format!(
+        "{}mod {};",
+        options.visibility.decl_prefix(),
+        final_module_name
+    ));
+    decl
+}
+
+/// Returns `file` (in the package `package`) with a `use` declaration (like
+/// `pub use skyd_derive::*;`) added at the top, where module declarations go. Returns `None` if
+/// the file already has it
+pub fn declare_use(
+    package: &Package,
+    file: &Path,
+    item: &str,
+    options: &ModuleOptions,
+) -> Result<Option<String>> {
+    let contents = fs::read_to_string(file)?;
+    if contents.lines().any(|line| line.trim() == item) {
+        return Ok(None);
+    }
+    let mut patched = Vec::new();
+    prepend_item(item, &contents, options, &mut patched)?;
+    let patched = String::from_utf8(patched).map_err(|e| Error::Other(e.to_string()))?;
+    if !options.rustfmt {
+        return Ok(Some(patched));
+    }
+    let dir = file.parent().unwrap_or_else(|| Path::new(""));
+    let formatted = utils::rustfmt(&patched, package.edition(), dir)?;
+    Ok(Some(formatted.unwrap_or(patched)))
 }
 
 /// Patch the file with the updated data
 fn patch_file<W: Write>(
     final_module_name: &str,
     contents: &str,
-    options: ModuleOptions,
+    options: &ModuleOptions,
+    file: &mut W,
+) -> Result<()> {
+    prepend_item(
+        &declaration(final_module_name, options),
+        contents,
+        options,
+        file,
+    )
+}
+
+/// Patch the file, adding the module entry at the top of the inline module at `inline`. A
+/// single-line inline module is split over several lines to make room for it
+fn patch_inline<W: Write>(
+    inline: &[String],
+    final_module_name: &str,
+    contents: &str,
+    options: &ModuleOptions,
+    file: &mut W,
+) -> Result<()> {
+    let block = scan(contents)
+        .blocks
+        .into_iter()
+        .find(|block| block.path == inline)
+        .ok_or_else(|| Error::Other(format!("inline module `{}` not found", inline.join("::"))))?;
+    let lines: Vec<&str> = contents.lines().collect();
+    let indentation = |line: &str| line[..line.len() - line.trim_start().len()].to_owned();
+    let indent = indentation(lines[block.open]);
+    // use the indentation of the block's items, if it has any
+    let item_indent = lines[block.open + 1..block.close.max(block.open + 1)]
+        .iter()
+        .find(|line| !line.trim().is_empty())
+        .map_or_else(|| format!("{indent}    "), |line| indentation(line));
+    let mut patched = String::new();
+    for (idx, line) in lines.iter().enumerate() {
+        if idx == block.open && block.open == block.close {
+            let (head, rest) = line.split_once('{').unwrap_or((line, "}"));
+            let (items, tail) = rest.split_at(rest.rfind('}').unwrap_or(rest.len()));
+            patched.push_str(&format!("{} {{\n", head.trim_end()));
+            for decl in declaration(final_module_name, options).lines() {
+                patched.push_str(&format!("{item_indent}{decl}\n"));
+            }
+            for item in items.split(';').map(str::trim).filter(|i| !i.is_empty()) {
+                patched.push_str(&format!("{item_indent}{item};\n"));
+            }
+            patched.push_str(&format!("{indent}{tail}\n"));
+        } else {
+            patched.push_str(line);
+            patched.push('\n');
+            if idx == block.open {
+                for decl in declaration(final_module_name, options).lines() {
+                    patched.push_str(&format!("{item_indent}{decl}\n"));
+                }
+            }
+        }
+    }
+    if !contents.ends_with('\n') {
+        patched.pop();
+    }
+    file.write_all(patched.as_bytes())?;
+    Ok(())
+}
+
+/// Write `contents` with `mod_decl` (or any other item) on top, below the comment header if it's
+/// to be skipped
+fn prepend_item<W: Write>(
+    mod_decl: &str,
+    contents: &str,
+    options: &ModuleOptions,
     file: &mut W,
 ) -> Result<()> {
-    let mod_decl = if options.is_public {
-        format!("pub mod {};", final_module_name)
-    } else {
-        format!("mod {};", final_module_name)
-    };
     if contents.starts_with("/*") && options.from_comment_header_bottom {
         // starts with a comment and we have to append below it
         let mut comment_end_idx = contents.find("*/").ok_or_else(|| {
@@ -166,7 +825,7 @@ fn main() {
 
 ";
     let mut v = Vec::new();
-    patch_file("z", FILE_WITHOUT_COMMENT, options, &mut v).unwrap();
+    patch_file("z", FILE_WITHOUT_COMMENT, &options, &mut v).unwrap();
     assert_eq!(String::from_utf8_lossy(&v), FILE_WITHOUT_COMMENT_PATCHED);
 }
 
@@ -223,7 +882,7 @@ fn main() {
 }
 "#;
     let mut v = Vec::new();
-    patch_file("z", FILE_WITH_COMMENT, options, &mut v).unwrap();
+    patch_file("z", FILE_WITH_COMMENT, &options, &mut v).unwrap();
     assert_eq!(String::from_utf8_lossy(&v), FILE_WITH_COMMENT_PATCHED);
 }
 
@@ -278,6 +937,97 @@ fn main() {
 }
 "#;
     let mut v = Vec::new();
-    patch_file("z", FILE_WITH_COMMENT, options, &mut v).unwrap();
+    patch_file("z", FILE_WITH_COMMENT, &options, &mut v).unwrap();
     assert_eq!(String::from_utf8_lossy(&v), FILE_WITH_COMMENT_PATCHED);
 }
+
+#[test]
+fn scan_declarations_test() {
+    const FILE: &str = r#"/*
+ * mod commented;
+*/
+
+mod workspace;
+#[macro_use]
+mod macros;
+#[cfg(unix)]
+pub mod sys;
+pub(crate) mod errors;
+// mod disabled;
+
+mod inline {
+    mod nested;
+}
+
+fn main() {}
+"#;
+    let decls = scan_declarations(FILE);
+    let names: Vec<&str> = decls.iter().map(|d| d.name.as_str()).collect();
+    assert_eq!(names, ["workspace", "macros", "sys", "errors"]);
+    assert!(decls[1].macro_use);
+    assert_eq!(decls[2].cfg.as_deref(), Some("unix"));
+    assert_eq!(decls[2].visibility, Visibility::Public);
+    assert_eq!(
+        decls[3].visibility,
+        Visibility::Restricted("crate".to_owned())
+    );
+}
+
+#[test]
+fn inline_module_patch() {
+    const FILE: &str = "\
+mod errors;
+
+#[cfg(unix)]
+pub mod sys {
+    pub mod unix;
+
+    mod net {
+        pub(crate) mod tcp;
+    }
+}
+pub mod io { pub mod file; } // grouped
+
+fn main() {}
+";
+    let scan = scan(FILE);
+    let paths: Vec<String> = scan.blocks.iter().map(|b| b.path.join("::")).collect();
+    assert_eq!(paths, ["sys", "sys::net", "io"]);
+    assert_eq!((scan.blocks[0].open, scan.blocks[0].close), (3, 9));
+    assert_eq!((scan.blocks[1].open, scan.blocks[1].close), (6, 8));
+    let decls: Vec<String> = scan
+        .decls
+        .iter()
+        .map(|(scope, decl)| format!("{}:{}", scope.join("::"), decl.name))
+        .collect();
+    assert_eq!(decls, [":errors", "sys:unix", "sys::net:tcp", "io:file"]);
+    let patch = |inline: &[&str], name: &str| {
+        let inline: Vec<String> = inline.iter().map(|s| s.to_string()).collect();
+        let mut v = Vec::new();
+        patch_inline(&inline, name, FILE, &ModuleOptions::default(), &mut v).unwrap();
+        String::from_utf8(v).unwrap()
+    };
+    assert!(
+        patch(&["sys"], "windows").contains("pub mod sys {\n    mod windows;\n    pub mod unix;\n")
+    );
+    assert!(patch(&["sys", "net"], "udp")
+        .contains("    mod net {\n        mod udp;\n        pub(crate) mod tcp;\n    }\n"));
+    assert!(patch(&["io"], "pipe").contains(
+        "pub mod io {\n    mod pipe;\n    pub mod file;\n} // grouped\n\nfn main() {}\n"
+    ));
+}
+
+#[test]
+fn process_options_test() {
+    let mut options = ModuleOptions::default();
+    options
+        .process_options(&HashSet::from(["P", "fmt", "lib"]))
+        .unwrap();
+    assert_eq!(options.visibility, Visibility::Public);
+    assert!(options.rustfmt && options.no_mod_folder.is_none());
+    assert_eq!(options.target, Some(CrateTarget::Lib));
+    let mut options = ModuleOptions::default();
+    assert!(options
+        .process_options(&HashSet::from(["fmt", "bogus"]))
+        .is_err());
+}
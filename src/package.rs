@@ -15,41 +15,197 @@
 */
 
 #[cfg(test)]
-use std::{fs, io::Write, path::Path, process::Command};
+use std::{fs, io::Write, process::Command};
 use {
     crate::{
-        module::{self, ModuleOptions},
+        generated::{self, BuildScript},
+        module::{self, CreatedModule, ModuleOptions, Parent},
+        target::{self, MovedBin},
         utils, Error, Result,
     },
     cargo_toml::Package,
+    std::path::{Component, Path},
 };
 
-/// Create a module in a package (not a workspace)
+/// Create a module in a package (not a workspace). `rel_dir` is the directory the user is in,
+/// relative to the package root, which is used to resolve relative module paths
 pub fn create_module_in_package(
     path: &str,
     options: ModuleOptions,
-    _package: Package,
-) -> Result<()> {
-    _create_module_in_package(path, options)
+    package: Package,
+    rel_dir: &Path,
+) -> Result<CreatedModule> {
+    let root_file_name = target::root_file(&package, options.target.as_ref())?;
+    let (root, moving) = target::module_root(&root_file_name)?;
+    let context = module_context(rel_dir, &root.dir);
+    let context: Vec<&str> = context.iter().map(String::as_str).collect();
+    _create_module_in_package(&package, &root, moving, path, options, &context)
 }
 
-fn _create_module_in_package(path: &str, options: ModuleOptions) -> Result<()> {
+fn _create_module_in_package(
+    package: &Package,
+    root: &Parent,
+    mut moving: Option<MovedBin>,
+    path: &str,
+    mut options: ModuleOptions,
+    context: &[&str],
+) -> Result<CreatedModule> {
     // find module directory and file paths
+    let path_segments = resolve_path(path, context)?;
+    let mut module = module::plan_module(package, root, &path_segments, &options)?;
+    let script = if options.generated {
+        let file_name = generated::file_name(&path_segments);
+        options.contents = Some(generated::module_contents(&file_name));
+        BuildScript::prepare(package, &file_name, &path_segments.join("::"))?
+    } else {
+        None
+    };
+    if let Some(bin) = &moving {
+        bin.prepare(&mut module);
+    }
+    if let Some(script) = &script {
+        module.also_creates(script.path().to_path_buf());
+    }
+    // nothing is changed until the hook allows it
+    options.hooks.pre_create(&module.hook_context(package))?;
+    if let Some(bin) = &mut moving {
+        bin.apply()?;
+    }
+    let created = match &script {
+        // the build script is written first, so that the `post_create` hook sees it
+        Some(script) => script.write().and_then(|()| {
+            let created = module.create(package, options);
+            match &created {
+                Ok(_) => script.report(),
+                // the error that matters is the one that stopped the module from being created
+                Err(_) => {
+                    if let Err(e) = script.revert() {
+                        eprintln!("warning: failed to revert the build script: {e}");
+                    }
+                }
+            }
+            created
+        }),
+        None => module.create(package, options),
+    };
+    if let (Err(_), Some(bin)) = (&created, &moving) {
+        if let Err(e) = bin.revert() {
+            eprintln!("warning: failed to move the binary back: {e}");
+        }
+    }
+    created
+}
+
+/// Returns the module for the directory `rel_dir` (relative to the package root). Directories
+/// outside the directory of the top-level modules `src_dir` (and those that can't be modules)
+/// map to the crate root
+fn module_context(rel_dir: &Path, src_dir: &Path) -> Vec<String> {
+    let mut context = Vec::new();
+    if let Ok(module_dir) = rel_dir.strip_prefix(src_dir) {
+        for component in module_dir.components() {
+            match component {
+                Component::Normal(name) => match name.to_str() {
+                    Some(name) if utils::validate_module_name(name).is_ok() => {
+                        context.push(name.to_owned())
+                    }
+                    _ => return Vec::new(),
+                },
+                Component::CurDir => {}
+                _ => return Vec::new(),
+            }
+        }
+    }
+    context
+}
+
+/// Resolve the module path `path` against the module `context`. Paths starting with `crate::`
+/// are absolute, while `self::` and `super::` (and unqualified paths) are relative to the
+/// context
+fn resolve_path<'a>(path: &'a str, context: &[&'a str]) -> Result<Vec<&'a str>> {
     let path_segments: Vec<&str> = path.split("::").collect();
     let has_empty = path_segments.iter().any(|s| s.is_empty());
     if has_empty {
         // this will handle special cases like: "", "::", "::a", "a::"
-        // TODO(@ohsayan): Support full paths starting with "::"
         return Err(Error::EmptyPath);
     }
-    let root_file_name = utils::get_root_file_name()?;
-    // create the module
-    module::create_module(root_file_name, &path_segments, options)
+    let mut resolved = context.to_vec();
+    let mut segments = path_segments.into_iter().peekable();
+    match segments.peek() {
+        Some(&"crate") => {
+            resolved.clear();
+            segments.next();
+        }
+        Some(&"self") => {
+            segments.next();
+        }
+        _ => {}
+    }
+    while segments.peek() == Some(&"super") {
+        segments.next();
+        if resolved.pop().is_none() {
+            return Error::other("`super` goes beyond the crate root");
+        }
+    }
+    resolved.extend(segments);
+    if resolved.is_empty() {
+        return Err(Error::EmptyPath);
+    }
+    if resolved
+        .iter()
+        .any(|segment| matches!(*segment, "crate" | "self" | "super"))
+    {
+        return Error::bad_module_name();
+    }
+    Ok(resolved)
+}
+
+#[test]
+fn resolve_path_test() {
+    let context = ["engine", "storage"];
+    assert_eq!(resolve_path("a", &[]).unwrap(), ["a"]);
+    assert_eq!(
+        resolve_path("compaction", &context).unwrap(),
+        ["engine", "storage", "compaction"]
+    );
+    assert_eq!(
+        resolve_path("self::a::b", &context).unwrap(),
+        ["engine", "storage", "a", "b"]
+    );
+    assert_eq!(resolve_path("crate::a", &context).unwrap(), ["a"]);
+    assert_eq!(resolve_path("super::super::a", &context).unwrap(), ["a"]);
+    assert!(resolve_path("super::super::super::a", &context).is_err());
+    assert!(resolve_path("::a", &context).is_err());
+    assert!(resolve_path("a::self", &context).is_err());
+    assert!(resolve_path("crate", &context).is_err());
+}
+
+#[test]
+fn module_context_test() {
+    let root = Path::new("src");
+    assert!(module_context(Path::new(""), root).is_empty());
+    assert!(module_context(Path::new("src"), root).is_empty());
+    assert!(module_context(Path::new("tests/common"), root).is_empty());
+    assert_eq!(
+        module_context(Path::new("src/engine/storage"), root),
+        ["engine", "storage"]
+    );
+    assert!(module_context(Path::new("src/not-a-module"), root).is_empty());
 }
 
 #[test]
 fn create_module_in_package_test() {
-    _create_module_in_package("protocol", ModuleOptions::default()).unwrap();
+    _create_module_in_package(
+        &Package::new("dab", "0.2.0"),
+        &Parent::root(Path::new(utils::MAIN_RS)),
+        None,
+        "protocol",
+        ModuleOptions {
+            no_mod_folder: Some(false),
+            ..Default::default()
+        },
+        &[],
+    )
+    .unwrap();
     assert!(Path::new("src/protocol").is_dir());
     assert!(Path::new("src/protocol/mod.rs").is_file());
     let cmd = Command::new("cargo").arg("build").output().unwrap();
@@ -58,7 +214,7 @@ fn create_module_in_package_test() {
         "{}",
         String::from_utf8_lossy(&cmd.stderr)
     );
-    utils::cowfile("src/main.rs", |file, contents| {
+    utils::cowfile("src/main.rs", None, |file, contents| {
         let contents = contents.to_owned().replace("mod protocol;\n", "");
         file.write_all(contents.as_bytes())?;
         Ok(())
@@ -0,0 +1,166 @@
+/*
+ * Copyright (c) 2022, Sayan Nandan <nandansayan@outlook.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use {
+    crate::{config::Config, layout, module::ModuleOptions, Error, Result},
+    cargo_toml::Manifest,
+};
+
+/// A module in a built-in preset
+struct PresetModule {
+    /// the path to the module
+    path: &'static str,
+    /// mark the module entry with `#[macro_use]`
+    macro_use: bool,
+    /// the initial contents of the module
+    template: &'static str,
+}
+
+impl PresetModule {
+    const fn new(path: &'static str, template: &'static str) -> Self {
+        Self {
+            path,
+            macro_use: false,
+            template,
+        }
+    }
+    const fn macro_use(path: &'static str, template: &'static str) -> Self {
+        Self {
+            path,
+            macro_use: true,
+            template,
+        }
+    }
+}
+
+const MACROS_TEMPLATE: &str = "\
+//! Crate-wide macros. Since this module is declared with `#[macro_use]`, the macros defined
+//! here are available to every module declared after it
+";
+
+const ERRORS_TEMPLATE: &str = "\
+use std::{
+    error::Error as StdErrTrait,
+    fmt::{Display, Formatter, Result as FmtResult},
+    io::Error as IoError,
+};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+/// Errors arising from this crate
+pub enum Error {
+    /// An I/O error
+    IoError(IoError),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Error::IoError(ioe) => write!(f, \"I/O error: {ioe}\"),
+        }
+    }
+}
+
+impl StdErrTrait for Error {}
+
+impl From<IoError> for Error {
+    fn from(e: IoError) -> Self {
+        Self::IoError(e)
+    }
+}
+";
+
+const CONFIG_TEMPLATE: &str = "\
+#[derive(Debug, Default)]
+/// The configuration
+pub struct Config {}
+";
+
+const RUNNER_TEMPLATE: &str = "\
+use crate::errors::Result;
+
+/// Run using the provided source of arguments (useful for testing)
+pub fn run(args: Vec<String>) -> Result<()> {
+    let _ = args;
+    Ok(())
+}
+";
+
+const UTILS_TEMPLATE: &str = "\
+//! Miscellaneous utilities
+";
+
+/// The built-in presets. Modules are declared in the order they're listed
+const BUILTIN_PRESETS: [(&str, &[PresetModule]); 2] = [
+    (
+        "service",
+        &[
+            PresetModule::macro_use("macros", MACROS_TEMPLATE),
+            PresetModule::new("errors", ERRORS_TEMPLATE),
+            PresetModule::new("config", CONFIG_TEMPLATE),
+            PresetModule::new("utils", UTILS_TEMPLATE),
+        ],
+    ),
+    (
+        "cli",
+        &[
+            PresetModule::macro_use("macros", MACROS_TEMPLATE),
+            PresetModule::new("errors", ERRORS_TEMPLATE),
+            PresetModule::new("runner", RUNNER_TEMPLATE),
+            PresetModule::new("utils", UTILS_TEMPLATE),
+        ],
+    ),
+];
+
+/// Create the module skeleton for the preset `name` in the current package. User-defined
+/// presets (from `dab.toml`) take precedence over the built-in ones
+pub fn init(name: &str, base: ModuleOptions, config: &Config, manifest: Manifest) -> Result<()> {
+    if manifest.package.is_none() {
+        return Error::other("`init` can only be used in a package");
+    }
+    if let Some(layout_file) = config.preset(name) {
+        return layout::apply_file(&layout_file.to_string_lossy(), base, manifest);
+    }
+    match BUILTIN_PRESETS.iter().find(|(preset, _)| *preset == name) {
+        Some((_, modules)) => {
+            let modules = modules
+                .iter()
+                .map(|module| {
+                    let options = ModuleOptions {
+                        macro_use: module.macro_use,
+                        contents: Some(module.template.to_owned()),
+                        ..base.clone()
+                    };
+                    (module.path.to_owned(), options)
+                })
+                .collect();
+            layout::apply(modules, base.target.as_ref(), manifest)
+        }
+        None => {
+            let available: Vec<&str> = config
+                .presets
+                .keys()
+                .map(String::as_str)
+                .chain(BUILTIN_PRESETS.iter().map(|(preset, _)| *preset))
+                .collect();
+            Error::other(format!(
+                "unknown preset `{name}`. available presets: {}",
+                available.join(", ")
+            ))
+        }
+    }
+}
@@ -0,0 +1,217 @@
+/*
+ * Copyright (c) 2022, Sayan Nandan <nandansayan@outlook.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use {
+    crate::{workspace, Error, Result},
+    std::{
+        fs,
+        path::{Path, PathBuf},
+    },
+    toml::Value,
+};
+
+/// `Cargo.toml`
+pub const CARGO_TOML: &str = "Cargo.toml";
+
+#[derive(Debug, PartialEq, Eq)]
+/// The package or workspace that `dab` operates on
+pub struct Root {
+    /// the directory of the nearest manifest (a package, or a virtual workspace)
+    pub dir: PathBuf,
+    /// the directory of the enclosing workspace root, if any. This is `dir` itself if the nearest
+    /// manifest is a workspace root
+    pub workspace_dir: Option<PathBuf>,
+}
+
+/// Discover the root the way `cargo` does: use the nearest `Cargo.toml` at or above `start` (or
+/// `manifest_path`, if provided) and then look for the enclosing workspace root
+pub fn discover(start: &Path, manifest_path: Option<&Path>) -> Result<Root> {
+    let manifest_path = match manifest_path {
+        Some(path) => {
+            if path.file_name().is_none_or(|name| name != CARGO_TOML) {
+                return Error::other("the manifest path must be a path to a `Cargo.toml` file");
+            }
+            if !path.is_file() {
+                return Error::other(format!("manifest `{}` doesn't exist", path.display()));
+            }
+            path.canonicalize()?
+        }
+        None => start
+            .ancestors()
+            .map(|dir| dir.join(CARGO_TOML))
+            .find(|manifest| manifest.is_file())
+            .ok_or_else(|| {
+                Error::Other(format!(
+                    "could not find `{CARGO_TOML}` in `{}` or any parent directory",
+                    start.display()
+                ))
+            })?,
+    };
+    let dir = manifest_path
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let manifest = read_raw_manifest(&manifest_path)?;
+    let workspace_dir = if manifest.get("workspace").is_some() {
+        Some(dir.clone())
+    } else {
+        find_workspace_root(&dir, &manifest)?
+    };
+    Ok(Root { dir, workspace_dir })
+}
+
+/// Find the workspace root for the package in `package_dir`. This is either the workspace
+/// pointed to by `package.workspace` or the first parent directory with a workspace manifest
+/// that doesn't exclude the package. Like `cargo`, it's an error if the package isn't a member of
+/// the workspace that's found
+fn find_workspace_root(package_dir: &Path, manifest: &Value) -> Result<Option<PathBuf>> {
+    let found = match manifest
+        .get("package")
+        .and_then(|package| package.get("workspace"))
+        .and_then(Value::as_str)
+    {
+        Some(explicit) => {
+            let dir = workspace::normalize(&package_dir.join(explicit));
+            let workspace = read_raw_manifest(&dir.join(CARGO_TOML))?
+                .get("workspace")
+                .cloned()
+                .ok_or_else(|| {
+                    Error::Other(format!(
+                        "`package.workspace` points to `{}`, which isn't a workspace",
+                        dir.display()
+                    ))
+                })?;
+            Some((dir, workspace))
+        }
+        None => {
+            let mut found = None;
+            for dir in package_dir.ancestors().skip(1) {
+                let manifest_path = dir.join(CARGO_TOML);
+                if !manifest_path.is_file() {
+                    continue;
+                }
+                if let Some(workspace) = read_raw_manifest(&manifest_path)?.get("workspace") {
+                    if !is_excluded(dir, workspace, package_dir) {
+                        found = Some((dir.to_path_buf(), workspace.clone()));
+                        break;
+                    }
+                }
+            }
+            found
+        }
+    };
+    let Some((dir, workspace)) = found else {
+        return Ok(None);
+    };
+    let members = workspace::expand_members(
+        &dir,
+        &string_list(&workspace, "members"),
+        &string_list(&workspace, "exclude"),
+    )?;
+    if !members.contains(&workspace::normalize(package_dir)) {
+        return Error::other(format!(
+            "the package in `{}` believes it's in the workspace at `{}` when it's not. Add it \
+            to `workspace.members`, or keep it out with `workspace.exclude` (or an empty \
+            `[workspace]` in its own manifest)",
+            package_dir.display(),
+            dir.display()
+        ));
+    }
+    Ok(Some(dir))
+}
+
+/// Returns true if the workspace (in `workspace_dir`) excludes the package in `package_dir`
+fn is_excluded(workspace_dir: &Path, workspace: &Value, package_dir: &Path) -> bool {
+    workspace::is_excluded(
+        workspace_dir,
+        &string_list(workspace, "members"),
+        &string_list(workspace, "exclude"),
+        package_dir,
+    )
+}
+
+/// Returns the list of strings at `key` in the workspace table
+fn string_list(workspace: &Value, key: &str) -> Vec<String> {
+    workspace
+        .get(key)
+        .and_then(Value::as_array)
+        .map(|list| {
+            list.iter()
+                .filter_map(Value::as_str)
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Read a manifest without interpreting it
+pub fn read_raw_manifest(path: &Path) -> Result<Value> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| Error::Other(format!("Couldn't read `{}`: {e}", path.display())))?;
+    Ok(toml::from_str(&contents)?)
+}
+
+#[test]
+fn discover_from_subdirectory() {
+    let cd = std::env::current_dir().unwrap();
+    let root = discover(&cd.join("src"), None).unwrap();
+    assert_eq!(
+        root,
+        Root {
+            dir: cd.clone(),
+            workspace_dir: None
+        }
+    );
+    let explicit = discover(Path::new("/"), Some(&cd.join(CARGO_TOML))).unwrap();
+    assert_eq!(explicit, root);
+    assert!(discover(&cd, Some(&cd.join("src"))).is_err());
+}
+
+#[test]
+fn discover_nested_and_excluded() {
+    let base = std::env::temp_dir().join(format!("dab-discover-{}", std::process::id()));
+    let package = |dir: &str, extra: &str| {
+        fs::create_dir_all(base.join(dir)).unwrap();
+        fs::write(
+            base.join(dir).join(CARGO_TOML),
+            format!("[package]\nname = \"x\"\n{extra}"),
+        )
+        .unwrap();
+    };
+    fs::create_dir_all(&base).unwrap();
+    fs::write(
+        base.join(CARGO_TOML),
+        "[workspace]\nmembers = [\"crates/*\", \"tools/keep\"]\nexclude = [\"tools\"]\n",
+    )
+    .unwrap();
+    package("crates/member", "");
+    package("tools/gen", "");
+    package("tools/keep", "");
+    package("stray", "");
+    package("inner", "[workspace]\n");
+    package("inner/nested", "");
+    let workspace_of = |dir: &str| discover(&base.join(dir), None).map(|root| root.workspace_dir);
+    assert_eq!(workspace_of("crates/member").unwrap(), Some(base.clone()));
+    // excluded, unless it's listed explicitly
+    assert_eq!(workspace_of("tools/gen").unwrap(), None);
+    assert_eq!(workspace_of("tools/keep").unwrap(), Some(base.clone()));
+    // in the workspace directory, but not a member
+    assert!(workspace_of("stray").is_err());
+    // a nested workspace is its own workspace
+    assert_eq!(workspace_of("inner").unwrap(), Some(base.join("inner")));
+    assert!(workspace_of("inner/nested").is_err());
+    fs::remove_dir_all(&base).unwrap();
+}
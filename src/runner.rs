@@ -15,9 +15,22 @@
 */
 
 use {
-    crate::{module::ModuleOptions, package, workspace, Error, Result},
-    cargo_toml::Manifest,
-    std::{collections::HashSet, fs},
+    crate::{
+        config::Config,
+        layout,
+        module::{CreatedModule, ModuleOptions},
+        package, preset,
+        root::{self, Root},
+        target::{self, CrateTarget},
+        workspace::{self, MemberKind, Members},
+        Error, Result,
+    },
+    cargo_toml::{Manifest, Package, Workspace},
+    std::{
+        collections::{HashMap, HashSet},
+        env,
+        path::Path,
+    },
 };
 
 /// The help menu
@@ -32,15 +45,76 @@ also adding `mod errors.rs` to the root file (`lib.rs` or `main.rs` depending on
 type)
 
 USAGE:
-    dab [FLAGS]
+    dab [FLAGS] <MODULE>
+    dab [FLAGS] <SUBCOMMAND>
 
 FLAGS:
     --help       Prints help information
     --public,-P  Make the new module public
     --cskip,-C   Skip the comment header (if any)
-    --dskip,-D   Skip creating module directory (only module.rs)  
+    --dskip,-D   Create `module.rs` instead of `module/mod.rs` (the default from the 2018
+                 edition)
+    --mod-folder,-M
+                 Create `module/mod.rs` instead of `module.rs` (the default in the 2015 edition)
+    --fmt,-F     Run `rustfmt` on the file that gets the module entry
+    --edit,-E    Open the new module in your editor (`$VISUAL` or `$EDITOR`)
+    --common     Share the `tests/common` module with the test (with `test`)
+    --reexport   Re-export the macros from the library they're for (with `proc-macro`)
+    --lib        Use the library of a package that also has a binary (or create a library
+                 package with `new-member`)
+    --force      Don't ask before deleting the package (with `rm-member`)
+
+OPTIONS:
+    --preset <NAME>         The preset to use with `init`
+    --manifest-path <PATH>  Path to the `Cargo.toml` to use. By default, the nearest `Cargo.toml`
+                            in the current directory or any of its parents is used
+    --bin <NAME>            Use the binary NAME of a package that also has a library. Without
+                            `--lib` or `--bin`, you're asked which one to use (or the library is
+                            used if `dab` isn't run from a terminal)
+    --features <FEATURES>   The features that the new example needs (separated by commas or
+                            spaces)
+    --for <NAME>            The package that a new proc-macro package is for
+    --package,-p <NAME>     The package to use. By default, this is the package in the module
+                            path (`pkg::module`) or the one you're in
+
+SUBCOMMANDS:
+    apply <FILE>          Create the modules described in a layout file, leaving existing
+                          modules alone and reporting any drift
+    export-layout [FILE]  Write the module layout of the current crate to FILE (or stdout)
+    init --preset <NAME>  Create the module skeleton of a built-in or user-defined preset in the
+                          current package
+    new-member <PATH>     Create a new package at PATH (relative to the workspace root) and add
+                          it to the workspace
+    proc-macro <NAME> --for <PKG>
+                          Create the proc-macro package NAME next to PKG (or at the path NAME)
+                          with a sample derive, and add it as a dependency of PKG
+    rm-member <PKG>       Remove the package PKG from the workspace and delete its directory,
+                          along with the dependencies on it (if no code uses it anymore)
+    mv-member <OLD> <NEW> Rename the package OLD (and its directory) to NEW, updating the workspace,
+                          the dependencies on it and the `OLD::` paths in the crates using it
+    test <NAME>           Create the integration test `tests/NAME.rs`. With `--common`, the test
+                          gets the `common` module shared by the tests (created if needed)
+    example <NAME>        Create the example `examples/NAME.rs`, requiring the `--features` (if
+                          any)
+    bench <NAME>          Create the benchmark `benches/NAME.rs` (with `harness = false`)
+    generated <MODULE>    Create a module that includes `MODULE.rs` from `OUT_DIR`, and the code
+                          that writes it to the build script
+    dep <PKG> -> <DEP>    Add the workspace member DEP as a path dependency of the member PKG
+                          (or inherit it from `[workspace.dependencies]` if the workspace has it)
 ";
 
+/// Options that take a value (`--option value` or `--option=value`)
+const VALUE_OPTIONS: [&str; 6] = [
+    "preset",
+    "manifest-path",
+    "package",
+    "bin",
+    "features",
+    "for",
+];
+/// The short forms of the options that take a value
+const SHORT_VALUE_OPTIONS: [(&str, &str); 1] = [("p", "package")];
+
 /// Run `dab` using the provided source of arguments (useful for testing)
 pub fn run(args: Vec<String>) -> Result<()> {
     if args.is_empty() {
@@ -49,40 +123,323 @@ pub fn run(args: Vec<String>) -> Result<()> {
 
     // process module options
     let mut options = HashSet::new();
-    let mut module = None;
-    for arg in args.iter() {
-        if let Some(stripped) = arg.strip_prefix("--") {
-            if !options.insert(stripped) {
+    let mut values = HashMap::new();
+    let mut positional = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        // `-X` is the short form of an option (but `->` isn't an option)
+        let stripped = arg.strip_prefix("--").or_else(|| {
+            arg.strip_prefix('-')
+                .filter(|short| short.starts_with(|c: char| c.is_ascii_alphabetic()))
+        });
+        if let Some(stripped) = stripped {
+            let (option, value) = match stripped.split_once('=') {
+                Some((option, value)) => (option, Some(value)),
+                None => (stripped, None),
+            };
+            let option = SHORT_VALUE_OPTIONS
+                .iter()
+                .find(|(short, _)| *short == option)
+                .map_or(option, |(_, long)| long);
+            if VALUE_OPTIONS.contains(&option) {
+                let value = match value {
+                    Some(value) => value,
+                    None => args.next().map(String::as_str).ok_or_else(|| {
+                        Error::Other(format!("expected a value for `--{option}`"))
+                    })?,
+                };
+                if values.insert(option, value).is_some() {
+                    return Error::other("duplicate options specified");
+                }
+            } else if !options.insert(stripped) {
                 return Error::other("duplicate options specified");
             }
-        } else if module.is_none() {
-            module = Some(arg.as_str());
         } else {
-            return Error::other("expected one module name");
+            positional.push(arg.as_str());
         }
     }
     let mut modoption = ModuleOptions::default();
     modoption.process_options(&options)?;
+    if let Some(bin) = values.get("bin") {
+        if modoption.target.is_some() {
+            return Error::other("`--lib` and `--bin` can't be used together");
+        }
+        modoption.target = Some(CrateTarget::Bin(Some(bin.to_string())));
+    }
 
     // handle help message case
     if modoption.is_help {
         println!("{HELP}");
         return Ok(());
-    } else if module.is_none() {
+    }
+    // find the package or workspace we're in and switch to it
+    let cwd = env::current_dir()?;
+    let root = root::discover(&cwd, values.get("manifest-path").map(Path::new))?;
+    env::set_current_dir(&root.dir)?;
+    let config = Config::discover(&root)?;
+    modoption.hooks = config.hooks.clone();
+    modoption.rustfmt |= config.rustfmt;
+    if modoption.target.is_none() {
+        modoption.target = config.default_target.clone();
+    }
+
+    let package = values.get("package").copied();
+
+    match positional.as_slice() {
         // all options; no module? that's broken
-        return Error::other("Expected module name. Only found options. Run `--help` for usage");
+        [] => Error::other("Expected module name. Only found options. Run `--help` for usage"),
+        // file arguments are relative to where the user is, not to the root we switched to
+        ["apply", layout_file] => layout::apply_file(
+            &cwd.join(layout_file).to_string_lossy(),
+            modoption,
+            read_manifest()?,
+        ),
+        ["apply", ..] => Error::other("expected one layout file"),
+        ["export-layout"] => layout::export(None, modoption.target.as_ref(), read_manifest()?),
+        ["export-layout", out] => {
+            let out = cwd.join(out);
+            let out = out.to_string_lossy();
+            layout::export(Some(&out), modoption.target.as_ref(), read_manifest()?)
+        }
+        ["export-layout", ..] => Error::other("expected at most one output file"),
+        ["init"] => match values.get("preset") {
+            Some(name) => preset::init(name, modoption, &config, read_manifest()?),
+            None => Error::other("expected a preset. Run `--help` for usage"),
+        },
+        ["init", ..] => Error::other("`init` takes no arguments other than `--preset`"),
+        ["new-member", path] => {
+            let workspace = enter_workspace(&root, "new-member")?;
+            let kind = if options.contains("lib") {
+                MemberKind::Lib
+            } else {
+                MemberKind::Bin
+            };
+            workspace::new_member(path, kind, &workspace)
+        }
+        ["new-member", ..] => Error::other("expected one package path"),
+        ["proc-macro", path] => {
+            let for_package = values.get("for").copied().or(package).ok_or_else(|| {
+                Error::Other("expected the package the macros are for (`--for <PACKAGE>`)".into())
+            })?;
+            let workspace = enter_workspace(&root, "proc-macro")?;
+            workspace::new_proc_macro(path, for_package, &modoption, &workspace)
+        }
+        ["proc-macro", ..] => Error::other("expected one package name or path"),
+        ["rm-member", name] => {
+            let workspace = enter_workspace(&root, "rm-member")?;
+            workspace::remove_member(name, options.contains("force"), &workspace)
+        }
+        ["rm-member", ..] => Error::other("expected one package name"),
+        ["mv-member", old, new] => {
+            let workspace = enter_workspace(&root, "mv-member")?;
+            workspace::move_member(old, new, &workspace)
+        }
+        ["mv-member", ..] => Error::other("expected the current and the new package name"),
+        // `->` has to be quoted in most shells, so it's optional
+        ["dep", from, "->", to] | ["dep", from, to] => {
+            let workspace = enter_workspace(&root, "dep")?;
+            workspace::add_dependency(from, to, &workspace)
+        }
+        ["dep", dependency] if dependency.contains("->") => {
+            let (from, to) = dependency.split_once("->").unwrap_or_default();
+            let workspace = enter_workspace(&root, "dep")?;
+            workspace::add_dependency(from.trim(), to.trim(), &workspace)
+        }
+        ["dep", ..] => Error::other("expected `<PACKAGE> -> <DEPENDENCY>`"),
+        ["test", name] => {
+            let edit = modoption.is_edit || config.edit;
+            let created = in_package(&root, &cwd, package, |package| {
+                target::new_test(&package, name, &modoption)
+            })?;
+            open_editor(&config, edit, name, &created)
+        }
+        ["test", ..] => Error::other("expected one test name"),
+        ["example", name] => {
+            let edit = modoption.is_edit || config.edit;
+            // like `cargo`, features are separated by commas or spaces
+            let features: Vec<&str> = values
+                .get("features")
+                .map(|features| {
+                    features
+                        .split([',', ' '])
+                        .filter(|f| !f.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default();
+            let created = in_package(&root, &cwd, package, |_| {
+                target::new_example(&read_manifest()?, name, &features)
+            })?;
+            open_editor(&config, edit, name, &created)
+        }
+        ["example", ..] => Error::other("expected one example name"),
+        ["bench", name] => {
+            let edit = modoption.is_edit || config.edit;
+            let created = in_package(&root, &cwd, package, |_| {
+                target::new_bench(&read_manifest()?, name, &config.bench)
+            })?;
+            open_editor(&config, edit, name, &created)
+        }
+        ["bench", ..] => Error::other("expected one benchmark name"),
+        ["generated", module] => {
+            let edit = modoption.is_edit || config.edit;
+            modoption.generated = true;
+            let created = create_module(module, modoption, &root, &cwd, package)?;
+            open_editor(&config, edit, module, &created)
+        }
+        ["generated", ..] => Error::other("expected one module name"),
+        [module] => {
+            let edit = modoption.is_edit || config.edit;
+            let created = create_module(module, modoption, &root, &cwd, package)?;
+            open_editor(&config, edit, module, &created)
+        }
+        _ => Error::other("expected one module name"),
     }
+}
+
+/// Switch to the root of the workspace that we're in, for `command`s that only make sense in a
+/// workspace
+fn enter_workspace(root: &Root, command: &str) -> Result<Workspace> {
+    let workspace_dir = root
+        .workspace_dir
+        .as_ref()
+        .ok_or_else(|| Error::Other(format!("`{command}` can only be used in a workspace")))?;
+    env::set_current_dir(workspace_dir)?;
+    read_manifest()?.workspace.ok_or(Error::EmptyManifest)
+}
+
+/// Read the `Cargo.toml` in the current directory
+fn read_manifest() -> Result<Manifest> {
+    // use an absolute path so that the workspace root can be found for any inherited fields
+    Ok(Manifest::from_path(
+        env::current_dir()?.join(root::CARGO_TOML),
+    )?)
+}
+
+/// Create the module at `module` in the package `package` (from `--package`) or else in the
+/// package or workspace in the current directory. Relative module paths are resolved against
+/// `cwd`, the directory the user is in
+fn create_module(
+    module: &str,
+    modoption: ModuleOptions,
+    root: &Root,
+    cwd: &Path,
+    package: Option<&str>,
+) -> Result<CreatedModule> {
+    if let Some(package) = package {
+        return create_module_in(package, module, modoption, root, cwd);
+    }
+    let crate_cfg = read_manifest()?;
+    match (crate_cfg.package, crate_cfg.workspace) {
+        (Some(package), Some(workspace)) => {
+            // a root package that's also a workspace: `member::path` targets a member
+            let members = Members::load(&root.dir, &workspace)?;
+            let path_segments: Vec<&str> = module.split("::").collect();
+            match members.qualifier(&path_segments, Some(&package.name)) {
+                Some(member) => workspace::create_module_in_member(
+                    &members,
+                    member,
+                    workspace::unqualified(module),
+                    modoption,
+                    cwd,
+                ),
+                None => {
+                    let member = members.containing(cwd).unwrap_or(&package.name);
+                    workspace::create_module_in_member(&members, member, module, modoption, cwd)
+                }
+            }
+        }
+        (Some(package), None) => {
+            // in a workspace member, `member::path` targets another member
+            if let Some(workspace_dir) = &root.workspace_dir {
+                let manifest = Manifest::from_path(workspace_dir.join(root::CARGO_TOML))?;
+                let workspace = manifest.workspace.ok_or(Error::EmptyManifest)?;
+                let members = Members::load(workspace_dir, &workspace)?;
+                let path_segments: Vec<&str> = module.split("::").collect();
+                if let Some(member) = members.qualifier(&path_segments, Some(&package.name)) {
+                    return workspace::create_module_in_member(
+                        &members,
+                        member,
+                        workspace::unqualified(module),
+                        modoption,
+                        cwd,
+                    );
+                }
+            }
+            let rel_dir = cwd
+                .strip_prefix(&root.dir)
+                .unwrap_or_else(|_| Path::new(""));
+            package::create_module_in_package(module, modoption, package, rel_dir)
+        }
+        (None, Some(workspace)) => {
+            workspace::create_module_in_workspace(module, modoption, workspace, cwd)
+        }
+        (None, None) => Err(Error::EmptyManifest),
+    }
+}
+
+/// Create the module at `module` in the package called `package`, which is either a member of
+/// the enclosing workspace or the current package
+fn create_module_in(
+    package: &str,
+    module: &str,
+    modoption: ModuleOptions,
+    root: &Root,
+    cwd: &Path,
+) -> Result<CreatedModule> {
+    in_package(root, cwd, Some(package), |package| {
+        let rel_dir = cwd
+            .strip_prefix(env::current_dir()?)
+            .unwrap_or_else(|_| Path::new(""));
+        package::create_module_in_package(module, modoption, package, rel_dir)
+    })
+}
+
+/// Run `f` from within the package called `package`, which is either a member of the enclosing
+/// workspace or the current package. Without a name, this is the current package or else the
+/// workspace member that the user is in (`cwd`) or the default member
+fn in_package<T>(
+    root: &Root,
+    cwd: &Path,
+    package: Option<&str>,
+    f: impl FnOnce(Package) -> Result<T>,
+) -> Result<T> {
+    match (package, read_manifest()?.package, &root.workspace_dir) {
+        (None, Some(own), _) => f(own),
+        (Some(name), Some(own), None) if own.name == name => f(own),
+        (Some(name), Some(own), None) => Error::other(format!(
+            "package `{name}` not found (available packages: `{}`)",
+            own.name
+        )),
+        (_, _, Some(workspace_dir)) => {
+            let manifest = Manifest::from_path(workspace_dir.join(root::CARGO_TOML))?;
+            let workspace = manifest.workspace.ok_or(Error::EmptyManifest)?;
+            let members = Members::load(workspace_dir, &workspace)?;
+            let member = match package {
+                Some(name) => name,
+                None => members
+                    .containing(cwd)
+                    .or(members.default_member())
+                    .ok_or_else(|| {
+                        Error::Other(format!(
+                            "can't tell which package to use. Use `--package <PACKAGE>` \
+                            (available packages: {})",
+                            members.available()
+                        ))
+                    })?,
+            };
+            members.in_member(member, f)
+        }
+        (_, None, None) => Err(Error::EmptyManifest),
+    }
+}
 
-    // validate module name
-    let module = module.unwrap();
-
-    // read Cargo.toml
-    let read_file = fs::read_to_string("Cargo.toml")
-        .map_err(|_| Error::Other("Couldn't read `Cargo.toml`".to_owned()))?;
-    let crate_cfg = Manifest::from_str(&read_file)?;
-    if crate_cfg.package.is_some() {
-        package::create_module_in_package(module, modoption, crate_cfg.package.unwrap())
-    } else {
-        workspace::create_module_in_workspace(module, modoption, crate_cfg.workspace.unwrap())
+/// Open the file that was created for `name` in the editor, if `edit` is set
+fn open_editor(config: &Config, edit: bool, name: &str, created: &CreatedModule) -> Result<()> {
+    if edit {
+        config
+            .editor
+            .open(&created.file, created.first_line)
+            .map_err(|e| Error::Other(format!("created `{name}`, but {e}")))?;
     }
+    Ok(())
 }
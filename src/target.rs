@@ -0,0 +1,624 @@
+/*
+ * Copyright (c) 2022, Sayan Nandan <nandansayan@outlook.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use {
+    crate::{
+        manifest,
+        module::{self, CreatedModule, ModuleOptions, Parent, PlannedModule},
+        root::CARGO_TOML,
+        transaction::Transaction,
+        utils, workspace, Error, Result,
+    },
+    cargo_toml::{Manifest, Package},
+    serde::Deserialize,
+    std::{
+        collections::BTreeSet,
+        env, fs,
+        io::{self, IsTerminal, Write},
+        iter,
+        path::{Path, PathBuf},
+    },
+    toml_edit::{Array, Item, Table},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+/// The crate of a package that modules are created in, for packages that have more than one
+pub enum CrateTarget {
+    /// the library (`--lib`)
+    Lib,
+    /// a binary (`--bin <name>`), or the one named after the package if no name is given
+    Bin(Option<String>),
+}
+
+impl TryFrom<String> for CrateTarget {
+    type Error = Error;
+    /// Parse the target from the configuration: `lib`, `bin` or `bin:<name>`
+    fn try_from(target: String) -> Result<Self> {
+        match target.split_once(':') {
+            None if target == "lib" => Ok(Self::Lib),
+            None if target == "bin" => Ok(Self::Bin(None)),
+            Some(("bin", name)) if !name.is_empty() => Ok(Self::Bin(Some(name.to_owned()))),
+            _ => Error::other(format!(
+                "bad target `{target}`. expected `lib`, `bin` or `bin:<name>`"
+            )),
+        }
+    }
+}
+
+/// Returns the root file of `target` in the package in the current directory, as given by the
+/// targets in its manifest (including the ones that `cargo` discovers, like `src/bin/*.rs`). If
+/// the package has both a library and a binary and there's no target, the user is asked which
+/// one to use (or the library is used, if there's no one to ask)
+pub fn root_file(package: &Package, target: Option<&CrateTarget>) -> Result<String> {
+    let roots = Roots::load()?;
+    let root = match target {
+        Some(CrateTarget::Lib) => roots
+            .lib
+            .as_deref()
+            .ok_or_else(|| Error::Other(format!("package `{}` has no library", package.name)))?,
+        Some(CrateTarget::Bin(name)) => roots.bin(&package.name, name.as_deref())?,
+        None => match (roots.lib.as_deref(), roots.main_bin(&package.name)) {
+            (Some(lib), Some(bin)) => ask(package, lib, bin)?,
+            (Some(lib), None) => lib,
+            (None, _) if roots.bins.is_empty() => {
+                return Error::other(format!(
+                    "package `{}` has neither a library nor a binary",
+                    package.name
+                ))
+            }
+            (None, _) => roots.bin(&package.name, None)?,
+        },
+    };
+    if !Path::new(root).is_file() {
+        return Error::other(format!(
+            "the root file `{root}` of package `{}` doesn't exist",
+            package.name
+        ));
+    }
+    Ok(root.to_owned())
+}
+
+/// The directory of the binaries that `cargo` discovers on its own
+const BIN_DIR: &str = "src/bin";
+/// The root file of a binary in a directory of its own
+const MAIN: &str = "main.rs";
+
+/// Returns the parent of the top-level modules of the crate with the root file `root_file`.
+/// Modules can't go next to a binary in `src/bin/<name>.rs` (they'd be binaries as well), so it
+/// has to be moved to `src/bin/<name>/main.rs` (along with its `[[bin]] path`, if it has one)
+/// before its modules are created, which the returned move does. Until then, the root is read
+/// where it is
+pub fn module_root(root_file: &str) -> Result<(Parent, Option<MovedBin>)> {
+    let root = workspace::normalize(Path::new(root_file));
+    let name = match root.file_stem().and_then(|name| name.to_str()) {
+        Some(name) if root.parent() == Some(Path::new(BIN_DIR)) => name,
+        _ => return Ok((Parent::root(Path::new(root_file)), None)),
+    };
+    let moved = format!("{BIN_DIR}/{name}/{MAIN}");
+    if Path::new(&moved).exists() {
+        return Error::other(format!(
+            "can't move `{root_file}` to `{moved}`, which already exists"
+        ));
+    }
+    let parent = Parent {
+        dir: module::child_dir(Path::new(&moved), true),
+        ..Parent::root(Path::new(root_file))
+    };
+    let bin = MovedBin {
+        from: root_file.to_owned(),
+        to: moved,
+        manifest: None,
+        created_dir: false,
+    };
+    Ok((parent, Some(bin)))
+}
+
+/// The move of a binary into a directory of its own (see [`module_root`])
+pub struct MovedBin {
+    from: String,
+    to: String,
+    /// the manifest from before the `[[bin]] path` was changed (`None` if it wasn't)
+    manifest: Option<String>,
+    /// the directory of the binary was created by the move
+    created_dir: bool,
+}
+
+impl MovedBin {
+    /// Returns the parent of the top-level modules, once the binary is moved
+    pub fn root(&self) -> Parent {
+        Parent::root(Path::new(&self.to))
+    }
+    /// Point `module` (the first module created for the binary) at the moved binary, which it
+    /// creates, as far as the hooks are concerned
+    pub fn prepare(&self, module: &mut PlannedModule) {
+        module.parent_moved(Path::new(&self.from), Path::new(&self.to));
+        module.also_creates(PathBuf::from(&self.to));
+    }
+    /// Move the binary, once the `pre_create` hook of the first module created for it has
+    /// allowed it
+    pub fn apply(&mut self) -> Result<()> {
+        let root = workspace::normalize(Path::new(&self.from));
+        let manifest_path = Path::new(CARGO_TOML);
+        let contents = fs::read_to_string(manifest_path)?;
+        let mut doc = manifest::parse(manifest_path, &contents)?;
+        let mut repointed = false;
+        let bins = doc.get_mut("bin").and_then(Item::as_array_of_tables_mut);
+        for bin in bins.into_iter().flat_map(|bins| bins.iter_mut()) {
+            let path = bin.get("path").and_then(Item::as_str).map(Path::new);
+            if path.is_some_and(|path| workspace::normalize(path) == root) {
+                manifest::set_str(bin, "path", &self.to);
+                repointed = true;
+            }
+        }
+        let mut transaction = Transaction::default();
+        if repointed {
+            transaction.write(manifest_path, doc.to_string());
+        }
+        transaction.rename(&root, &self.to);
+        let dir = Path::new(&self.to).parent().unwrap_or(Path::new(BIN_DIR));
+        self.created_dir = !dir.exists();
+        fs::create_dir_all(dir)?;
+        if let Err(e) = transaction.commit() {
+            if self.created_dir {
+                let _ = fs::remove_dir(dir);
+            }
+            return Err(e);
+        }
+        self.manifest = repointed.then_some(contents);
+        println!("Moved `{}` to `{}`", self.from, self.to);
+        Ok(())
+    }
+    /// Undo the move, for when the modules it was made for couldn't be created
+    pub fn revert(&self) -> Result<()> {
+        if let Some(contents) = &self.manifest {
+            fs::write(CARGO_TOML, contents)?;
+        }
+        fs::rename(&self.to, &self.from)?;
+        if let Some(dir) = Path::new(&self.to).parent().filter(|_| self.created_dir) {
+            let _ = fs::remove_dir(dir);
+        }
+        println!("Moved `{}` back to `{}`", self.to, self.from);
+        Ok(())
+    }
+}
+
+/// The directory of the integration tests
+const TESTS_DIR: &str = "tests";
+/// The module that's shared by the integration tests
+const COMMON: &str = "common";
+/// The contents of a new `common` module
+const COMMON_TEMPLATE: &str = "//! Helpers shared by the integration tests\n";
+
+/// Create the integration test `name` (`tests/<name>.rs`) in the package in the current
+/// directory. If `options.common` is set, the test declares the `common` module that's shared
+/// by the tests (`tests/common/mod.rs`, created if needed). This can also be done for an
+/// existing test
+pub fn new_test(package: &Package, name: &str, options: &ModuleOptions) -> Result<CreatedModule> {
+    utils::validate_module_name(name)?;
+    // the name is also the name of the test function
+    utils::check_keyword(name, package.edition())?;
+    if name == COMMON {
+        return Error::other(format!(
+            "`{COMMON}` is the module shared by the tests. Create it with `dab test <name> --common`"
+        ));
+    }
+    let tests_dir = Path::new(TESTS_DIR);
+    let file = tests_dir.join(format!("{name}.rs"));
+    let exists = file.is_file();
+    if exists && !options.common {
+        return Error::other(format!("test `{name}` already exists"));
+    }
+    if options.common {
+        let common = utils::add_mod_rs(tests_dir.join(COMMON));
+        if !common.is_file() {
+            fs::create_dir_all(tests_dir.join(COMMON))?;
+            fs::write(&common, COMMON_TEMPLATE)?;
+            println!("Created `{}`", common.display());
+        }
+    }
+    if !exists {
+        fs::create_dir_all(tests_dir)?;
+        fs::write(&file, format!("#[test]\nfn {name}() {{}}\n"))?;
+        println!("Created test `{name}`");
+    }
+    if options.common {
+        let options = ModuleOptions {
+            from_comment_header_bottom: options.from_comment_header_bottom,
+            rustfmt: options.rustfmt,
+            ..Default::default()
+        };
+        module::declare_module(package, &file, COMMON, &options)?;
+    }
+    let first_line = fs::read_to_string(&file)?
+        .lines()
+        .position(|line| line.starts_with(&format!("fn {name}(")))
+        .map_or(1, |line| line + 1);
+    Ok(CreatedModule {
+        file: env::current_dir()?.join(file),
+        first_line,
+    })
+}
+
+/// The directory of the examples
+const EXAMPLES_DIR: &str = "examples";
+
+/// Create the example `name` (`examples/<name>.rs`) in the package in the current directory. If
+/// the example needs any `features`, it gets an `[[example]]` entry with `required-features`
+pub fn new_example(manifest: &Manifest, name: &str, features: &[&str]) -> Result<CreatedModule> {
+    utils::validate_module_name(name)?;
+    if let Some(package) = &manifest.package {
+        utils::check_keyword(name, package.edition())?;
+    }
+    let file = Path::new(EXAMPLES_DIR).join(format!("{name}.rs"));
+    if file.exists()
+        || manifest
+            .example
+            .iter()
+            .any(|e| e.name.as_deref() == Some(name))
+    {
+        return Error::other(format!("example `{name}` already exists"));
+    }
+    check_features(manifest, features)?;
+    fs::create_dir_all(EXAMPLES_DIR)?;
+    fs::write(&file, utils::MAIN_TEMPLATE)?;
+    if !features.is_empty() {
+        let entry = manifest::edit(Path::new(CARGO_TOML), |doc| {
+            let mut example = Table::new();
+            example.insert("name", toml_edit::value(name));
+            example.insert(
+                "required-features",
+                toml_edit::value(features.iter().copied().collect::<Array>()),
+            );
+            manifest::push_table(doc, "example", example)
+        });
+        if let Err(e) = entry {
+            fs::remove_file(&file)?;
+            return Err(e);
+        }
+    }
+    println!("Created example `{name}`");
+    Ok(CreatedModule {
+        file: env::current_dir()?.join(file),
+        first_line: 2,
+    })
+}
+
+/// Make sure that the package has all of `features`. These are the ones in `[features]`, along
+/// with the optional dependencies (including the platform-specific and build ones) that no
+/// feature refers to with `dep:`
+fn check_features(manifest: &Manifest, features: &[&str]) -> Result<()> {
+    // `dep:name` hides the implicit feature of the optional dependency `name`
+    let hidden: Vec<&str> = manifest
+        .features
+        .values()
+        .flatten()
+        .filter_map(|entry| entry.strip_prefix("dep:"))
+        .collect();
+    let targets = manifest.target.values();
+    let optional: BTreeSet<&String> = iter::once(&manifest.dependencies)
+        .chain(iter::once(&manifest.build_dependencies))
+        .chain(targets.flat_map(|target| [&target.dependencies, &target.build_dependencies]))
+        .flatten()
+        .filter(|(name, dependency)| dependency.optional() && !hidden.contains(&name.as_str()))
+        .map(|(name, _)| name)
+        .collect();
+    let available: Vec<&String> = manifest.features.keys().chain(optional).collect();
+    for feature in features {
+        // features of dependencies (`dep/feature`) are left to `cargo`
+        if !feature.contains('/') && !available.iter().any(|a| a == feature) {
+            let available: Vec<String> = available.iter().map(|a| format!("`{a}`")).collect();
+            return Error::other(format!(
+                "the package has no feature `{feature}` (available features: {})",
+                if available.is_empty() {
+                    "none".to_owned()
+                } else {
+                    available.join(", ")
+                }
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The directory of the benchmarks
+const BENCHES_DIR: &str = "benches";
+
+#[derive(Debug, Default, Clone, Deserialize)]
+/// The benchmark configuration (the `[bench]` table in `dab.toml`)
+pub struct BenchConfig {
+    /// the benchmark framework (like `criterion` or `divan`), added as a dev-dependency
+    pub framework: Option<String>,
+    /// the version of the framework. Required for frameworks that `dab` doesn't know about
+    pub version: Option<String>,
+}
+
+impl BenchConfig {
+    /// Returns the dev-dependency on the framework (name and version), if any
+    fn dependency(&self) -> Result<Option<(&str, &str)>> {
+        let Some(framework) = self.framework.as_deref() else {
+            return Ok(None);
+        };
+        let known = match framework {
+            "criterion" => Some("0.5"),
+            "divan" => Some("0.1"),
+            _ => None,
+        };
+        match self.version.as_deref().or(known) {
+            Some(version) => Ok(Some((framework, version))),
+            None => Error::other(format!(
+                "no version set for the benchmark framework `{framework}` (set `bench.version` in `dab.toml`)"
+            )),
+        }
+    }
+    /// Returns the template of the benchmark `name`
+    fn template(&self, name: &str) -> String {
+        match self.framework.as_deref() {
+            Some("criterion") => format!(
+                "\
+use criterion::{{criterion_group, criterion_main, Criterion}};
+
+fn {name}(c: &mut Criterion) {{
+    c.bench_function(\"{name}\", |b| b.iter(|| {{}}));
+}}
+
+criterion_group!(benches, {name});
+criterion_main!(benches);
+"
+            ),
+            Some("divan") => format!(
+                "\
+fn main() {{
+    divan::main();
+}}
+
+#[divan::bench]
+fn {name}() {{}}
+"
+            ),
+            _ => utils::MAIN_TEMPLATE.to_owned(),
+        }
+    }
+}
+
+/// Create the benchmark `name` (`benches/<name>.rs`) in the package in the current directory,
+/// along with its `[[bench]]` entry (with `harness = false`, since benchmarks have their own
+/// `main`). The framework in the configuration is added as a dev-dependency if it's missing
+pub fn new_bench(manifest: &Manifest, name: &str, config: &BenchConfig) -> Result<CreatedModule> {
+    utils::validate_module_name(name)?;
+    if let Some(package) = &manifest.package {
+        utils::check_keyword(name, package.edition())?;
+    }
+    let file = Path::new(BENCHES_DIR).join(format!("{name}.rs"));
+    if file.exists()
+        || manifest
+            .bench
+            .iter()
+            .any(|b| b.name.as_deref() == Some(name))
+    {
+        return Error::other(format!("benchmark `{name}` already exists"));
+    }
+    let dependency = config.dependency()?.filter(|(framework, _)| {
+        !manifest.dev_dependencies.contains_key(*framework)
+            && !manifest.dependencies.contains_key(*framework)
+    });
+    let template = config.template(name);
+    fs::create_dir_all(BENCHES_DIR)?;
+    fs::write(&file, &template)?;
+    let entry = manifest::edit(Path::new(CARGO_TOML), |doc| {
+        let mut bench = Table::new();
+        bench.insert("name", toml_edit::value(name));
+        bench.insert("harness", toml_edit::value(false));
+        manifest::push_table(doc, "bench", bench)?;
+        if let Some((framework, version)) = dependency {
+            manifest::table_mut(doc.as_table_mut(), "dev-dependencies")?
+                .insert(framework, toml_edit::value(version));
+        }
+        Ok(())
+    });
+    if let Err(e) = entry {
+        fs::remove_file(&file)?;
+        return Err(e);
+    }
+    println!("Created benchmark `{name}`");
+    if let Some((framework, version)) = dependency {
+        println!("Added `{framework} = \"{version}\"` to the dev-dependencies");
+    }
+    // the line after the benchmark function's signature, unless its body is empty
+    let first_line = template
+        .lines()
+        .enumerate()
+        .find(|(_, line)| line.starts_with(&format!("fn {name}(")))
+        .map_or(
+            2,
+            |(i, line)| if line.ends_with("{}") { i + 1 } else { i + 2 },
+        );
+    Ok(CreatedModule {
+        file: env::current_dir()?.join(file),
+        first_line,
+    })
+}
+
+/// The root files of the crates in a package (relative to the package root)
+struct Roots {
+    lib: Option<String>,
+    /// binary name -> root file
+    bins: Vec<(String, String)>,
+}
+
+impl Roots {
+    /// Read the roots from the manifest in the current directory
+    fn load() -> Result<Self> {
+        // the path has to be absolute so that `cargo_toml` can look for the targets
+        let manifest = Manifest::from_path(env::current_dir()?.join(CARGO_TOML))?;
+        Ok(Self {
+            lib: manifest.lib.and_then(|lib| lib.path),
+            bins: manifest
+                .bin
+                .into_iter()
+                .filter_map(|bin| Some((bin.name?, bin.path?)))
+                .collect(),
+        })
+    }
+    /// Returns the binary named after the package, if any
+    fn main_bin(&self, package: &str) -> Option<&str> {
+        self.bins
+            .iter()
+            .find(|(name, _)| name == package)
+            .map(|(_, path)| path.as_str())
+    }
+    /// Returns the binary `name`, or the main binary (the one named after the package, or the
+    /// only binary) if no name is given
+    fn bin(&self, package: &str, name: Option<&str>) -> Result<&str> {
+        let available = || {
+            let names: Vec<String> = self
+                .bins
+                .iter()
+                .map(|(name, _)| format!("`{name}`"))
+                .collect();
+            names.join(", ")
+        };
+        match (name, self.bins.as_slice()) {
+            (Some(name), bins) => bins
+                .iter()
+                .find(|(bin, _)| bin == name)
+                .map(|(_, path)| path.as_str())
+                .ok_or_else(|| {
+                    Error::Other(format!(
+                        "package `{package}` has no binary named `{name}` (available binaries: {})",
+                        available()
+                    ))
+                }),
+            (None, []) => Error::other(format!("package `{package}` has no binary")),
+            (None, [(_, path)]) => Ok(path),
+            (None, _) => self.main_bin(package).ok_or_else(|| {
+                Error::Other(format!(
+                    "package `{package}` has several binaries ({}). use `--bin <name>`",
+                    available()
+                ))
+            }),
+        }
+    }
+}
+
+/// Ask the user whether to use the library or the binary of `package`
+fn ask<'a>(package: &Package, lib: &'a str, bin: &'a str) -> Result<&'a str> {
+    if !io::stdin().is_terminal() {
+        return Ok(lib);
+    }
+    print!(
+        "Package `{}` has both a library and a binary. Use the [l]ibrary or the [b]inary? [l] ",
+        package.name
+    );
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    match answer.trim() {
+        "" | "l" | "lib" | "library" => Ok(lib),
+        "b" | "bin" | "binary" => Ok(bin),
+        answer => Error::other(format!(
+            "expected `l` or `b`, found `{answer}`. use `--lib` or `--bin <name>` instead"
+        )),
+    }
+}
+
+#[test]
+fn crate_target_test() {
+    let parse = |target: &str| CrateTarget::try_from(target.to_owned());
+    assert_eq!(parse("lib").unwrap(), CrateTarget::Lib);
+    assert_eq!(parse("bin").unwrap(), CrateTarget::Bin(None));
+    assert_eq!(
+        parse("bin:migrate").unwrap(),
+        CrateTarget::Bin(Some("migrate".to_owned()))
+    );
+    assert!(parse("bin:").is_err());
+    assert!(parse("example").is_err());
+}
+
+#[test]
+fn roots_bin_test() {
+    let bin = |name: &str, path: &str| (name.to_owned(), path.to_owned());
+    let roots = Roots {
+        lib: None,
+        bins: vec![
+            bin("migrate", "src/bin/migrate/main.rs"),
+            bin("skyd", "src/main.rs"),
+        ],
+    };
+    assert_eq!(roots.bin("skyd", None).unwrap(), "src/main.rs");
+    assert_eq!(
+        roots.bin("skyd", Some("migrate")).unwrap(),
+        "src/bin/migrate/main.rs"
+    );
+    assert!(roots.bin("skyd", Some("repair")).is_err());
+    // without a binary named after the package, there's no telling which one to use
+    assert!(roots.bin("sky", None).is_err());
+    let roots = Roots {
+        lib: None,
+        bins: vec![bin("tool", "src/bin/tool.rs")],
+    };
+    assert_eq!(roots.bin("sky", None).unwrap(), "src/bin/tool.rs");
+}
+
+#[test]
+fn check_features_test() {
+    let manifest = Manifest::from_str(
+        r#"
+[package]
+name = "sky"
+
+[dependencies]
+serde = { version = "1", optional = true }
+tokio = { version = "1", optional = true }
+
+[target.'cfg(unix)'.dependencies]
+libc = { version = "0.2", optional = true }
+
+[features]
+json = ["dep:serde"]
+"#,
+    )
+    .unwrap();
+    assert!(check_features(&manifest, &["json", "tokio", "serde/derive"]).is_ok());
+    assert!(check_features(&manifest, &["libc"]).is_ok());
+    assert!(check_features(&manifest, &["serde"]).is_err());
+    assert!(check_features(&manifest, &["tls"]).is_err());
+}
+
+#[test]
+fn bench_config_test() {
+    let config = |framework: &str, version: Option<&str>| BenchConfig {
+        framework: Some(framework.to_owned()),
+        version: version.map(str::to_owned),
+    };
+    assert_eq!(BenchConfig::default().dependency().unwrap(), None);
+    assert_eq!(
+        config("criterion", None).dependency().unwrap(),
+        Some(("criterion", "0.5"))
+    );
+    assert_eq!(
+        config("divan", Some("0.1.14")).dependency().unwrap(),
+        Some(("divan", "0.1.14"))
+    );
+    assert!(config("iai", None).dependency().is_err());
+    assert!(config("criterion", None)
+        .template("codec")
+        .contains("criterion_group!(benches, codec);"));
+    assert_eq!(
+        BenchConfig::default().template("codec"),
+        utils::MAIN_TEMPLATE
+    );
+}
@@ -0,0 +1,133 @@
+/*
+ * Copyright (c) 2022, Sayan Nandan <nandansayan@outlook.com>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+use {
+    crate::Result,
+    std::{
+        fs,
+        io::Write,
+        path::{Path, PathBuf},
+    },
+};
+
+/// A set of file system changes that are made together: if one of them fails, the ones that were
+/// already made are undone. Writes are made first, then new files, renames and finally removals,
+/// so writes use the paths from before any rename
+#[derive(Default)]
+pub struct Transaction {
+    writes: Vec<(PathBuf, String)>,
+    creates: Vec<(PathBuf, String)>,
+    renames: Vec<(PathBuf, PathBuf)>,
+    removals: Vec<PathBuf>,
+}
+
+/// How to undo a change that was made
+enum Undo {
+    Write(PathBuf, Vec<u8>),
+    Create(PathBuf),
+    CreateDir(PathBuf),
+    Rename { from: PathBuf, to: PathBuf },
+}
+
+impl Transaction {
+    /// Write `contents` to the existing file at `path`
+    pub fn write(&mut self, path: impl Into<PathBuf>, contents: String) {
+        self.writes.push((path.into(), contents));
+    }
+    /// Create the new file `path` with `contents`, along with any missing parent directories
+    pub fn create(&mut self, path: impl Into<PathBuf>, contents: String) {
+        self.creates.push((path.into(), contents));
+    }
+    /// Rename `from` to `to`
+    pub fn rename(&mut self, from: impl Into<PathBuf>, to: impl Into<PathBuf>) {
+        self.renames.push((from.into(), to.into()));
+    }
+    /// Remove the directory `dir` (and everything in it)
+    pub fn remove_dir(&mut self, dir: impl Into<PathBuf>) {
+        self.removals.push(dir.into());
+    }
+    /// Make all the changes, or none of them
+    pub fn commit(self) -> Result<()> {
+        let mut undo = Vec::new();
+        let mut trash = Vec::new();
+        let ret = self.apply(&mut undo, &mut trash);
+        if ret.is_err() {
+            for step in undo.into_iter().rev() {
+                // nothing more we can do if this fails
+                let _ = match step {
+                    Undo::Write(path, contents) => fs::write(path, contents),
+                    Undo::Create(path) => fs::remove_file(path),
+                    Undo::CreateDir(dir) => fs::remove_dir(dir),
+                    Undo::Rename { from, to } => fs::rename(to, from),
+                };
+            }
+        } else {
+            // the changes are made at this point, so leftovers are only worth a warning
+            for dir in trash {
+                if let Err(e) = fs::remove_dir_all(&dir) {
+                    eprintln!("warning: failed to remove `{}`: {e}", dir.display());
+                }
+            }
+        }
+        ret
+    }
+    fn apply(self, undo: &mut Vec<Undo>, trash: &mut Vec<PathBuf>) -> Result<()> {
+        for (path, contents) in self.writes {
+            let original = fs::read(&path)?;
+            fs::write(&path, contents)?;
+            undo.push(Undo::Write(path, original));
+        }
+        for (path, contents) in self.creates {
+            // the missing parents are created from the outermost one, and removed the other way
+            let mut missing: Vec<&Path> = path
+                .ancestors()
+                .skip(1)
+                .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+                .collect();
+            while let Some(dir) = missing.pop() {
+                fs::create_dir(dir)?;
+                undo.push(Undo::CreateDir(dir.to_owned()));
+            }
+            let mut file = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)?;
+            undo.push(Undo::Create(path));
+            file.write_all(contents.as_bytes())?;
+        }
+        for (from, to) in self.renames {
+            fs::rename(&from, &to)?;
+            undo.push(Undo::Rename { from, to });
+        }
+        // removals are made by moving the directory out of the way, so that they can be undone
+        for dir in self.removals {
+            let to = trash_path(&dir);
+            fs::rename(&dir, &to)?;
+            undo.push(Undo::Rename {
+                from: dir,
+                to: to.clone(),
+            });
+            trash.push(to);
+        }
+        Ok(())
+    }
+}
+
+/// Returns the path that a directory that's being removed is moved to
+fn trash_path(dir: &Path) -> PathBuf {
+    let name = dir.file_name().unwrap_or_default().to_string_lossy();
+    dir.with_file_name(format!(".{name}.dab-removed"))
+}
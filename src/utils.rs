@@ -16,9 +16,13 @@
 
 use {
     crate::{Error, Result},
+    cargo_toml::Edition,
     std::{
         fs::{self, File, OpenOptions},
+        io::{ErrorKind, Write},
         path::{Path, PathBuf},
+        process::{Command, Stdio},
+        thread,
     },
 };
 
@@ -26,21 +30,14 @@ use {
 pub const MAIN_RS: &str = "src/main.rs";
 /// `src/lib.rs`
 pub const LIB_RS: &str = "src/lib.rs";
-/// The root file look-up
-const ROOT_FILE: [&str; 2] = [LIB_RS, MAIN_RS];
 /// `mod.rs`
 pub const MOD_RS: &str = "mod.rs";
-
-/// Get the root file (`lib.rs` or `main.rs` depending on binary/library crate type)
-pub fn get_root_file_name() -> Result<&'static str> {
-    let is_lib = Path::new(LIB_RS).is_file();
-    let is_bin = Path::new(MAIN_RS).is_file();
-    if is_bin && is_lib {
-        Err(Error::Other("Current package contains both `lib.rs` and `main.rs`. Unable to determine package type".to_owned()))
-    } else {
-        Ok(ROOT_FILE[is_bin as usize])
-    }
+/// The root file for new binaries (and examples)
+pub const MAIN_TEMPLATE: &str = "\
+fn main() {
+    println!(\"Hello, world!\");
 }
+";
 
 /// Add `mod.rs` to the provided path. This is just for convenience
 pub fn add_mod_rs(path: impl Into<PathBuf>) -> PathBuf {
@@ -56,22 +53,99 @@ pub fn suffix(path: impl Into<PathBuf>, ext: impl AsRef<Path>) -> PathBuf {
 
 /// A COW-style file manipulation function. This fill open the original file, read its contents
 /// and create a new file on a separate path, allow the user to modify it and then it will replace
-/// the original file with the new file
-pub fn cowfile(orig: &str, with_open: impl FnOnce(&mut File, &str) -> Result<()>) -> Result<()> {
+/// the original file with the new file. If `rustfmt` is set, the new contents are formatted with
+/// `rustfmt` (for the given edition) before the original file is replaced. If anything fails, the
+/// original file is left untouched
+pub fn cowfile(
+    orig: &str,
+    rustfmt: Option<Edition>,
+    with_open: impl FnOnce(&mut File, &str) -> Result<()>,
+) -> Result<()> {
     // read the old file into memory
     let old_file_contents = fs::read_to_string(orig)?;
     // open the COW file
     let new = format!("{}_", orig);
     let mut new_file = OpenOptions::new().write(true).create_new(true).open(&new)?;
-    // do whatever the caller wants to
-    with_open(&mut new_file, &old_file_contents)?;
-    // fsync
-    new_file.sync_all()?;
+    let ret = (|| {
+        // do whatever the caller wants to
+        with_open(&mut new_file, &old_file_contents)?;
+        if let Some(edition) = rustfmt {
+            let unformatted = fs::read_to_string(&new)?;
+            let dir = Path::new(orig).parent().unwrap_or_else(|| Path::new(""));
+            if let Some(formatted) = self::rustfmt(&unformatted, edition, dir)? {
+                new_file = File::create(&new)?;
+                new_file.write_all(formatted.as_bytes())?;
+            }
+        }
+        // fsync
+        new_file.sync_all()?;
+        Ok(())
+    })();
+    if ret.is_err() {
+        // get rid of the COW file
+        let _ = fs::remove_file(&new);
+        return ret;
+    }
     // replace the file
     fs::rename(new, orig)?;
     Ok(())
 }
 
+/// Returns the edition as passed to `rustc` and `rustfmt`
+pub fn edition_str(edition: Edition) -> Result<&'static str> {
+    match edition {
+        Edition::E2015 => Ok("2015"),
+        Edition::E2018 => Ok("2018"),
+        Edition::E2021 => Ok("2021"),
+        Edition::E2024 => Ok("2024"),
+        // `Edition` is non-exhaustive, so an edition added to `cargo_toml` can't be a compile
+        // error. it's rejected instead of being passed on under the wrong name
+        edition => Error::other(format!("the edition {edition:?} isn't supported yet")),
+    }
+}
+
+/// Format `source` (the new contents of a file in `dir`) using the local `rustfmt`. Like for the
+/// file itself, `rustfmt` uses the nearest `rustfmt.toml` in `dir` or any of its parents (such
+/// as the one at the package or workspace root). Returns `None` (after warning the user) if
+/// `rustfmt` isn't installed
+pub fn rustfmt(source: &str, edition: Edition, dir: &Path) -> Result<Option<String>> {
+    let mut cmd = Command::new("rustfmt");
+    cmd.args(["--edition", edition_str(edition)?]);
+    // with the source on stdin, `rustfmt` looks for its configuration from the directory it runs in
+    if !dir.as_os_str().is_empty() {
+        cmd.current_dir(dir);
+    }
+    let child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            eprintln!("warning: `rustfmt` isn't installed. Skipping formatting");
+            return Ok(None);
+        }
+        Err(e) => return Error::other(format!("failed to run `rustfmt`: {e}")),
+    };
+    // write from a separate thread so that a large output can't block us
+    let mut stdin = child.stdin.take().unwrap();
+    let source = source.to_owned();
+    let writer = thread::spawn(move || stdin.write_all(source.as_bytes()));
+    let output = child.wait_with_output()?;
+    writer
+        .join()
+        .map_err(|_| Error::Other("failed to write to `rustfmt`".to_owned()))??;
+    if output.status.success() {
+        Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned()))
+    } else {
+        Error::other(format!(
+            "`rustfmt` failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
 /// Validate a module name. Rules:
 /// - Can only start with alphabetic chars
 /// - Can start with `_` only if the module name is longer than 2 bytes
@@ -98,3 +172,62 @@ pub fn validate_module_name(name: &str) -> Result<()> {
         Error::bad_module_name()
     }
 }
+
+/// The keywords that can't be used as names in any edition (strict and reserved keywords)
+const KEYWORDS: [&str; 47] = [
+    "as", "break", "const", "continue", "crate", "else", "enum", "extern", "false", "fn", "for",
+    "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return",
+    "self", "Self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use", "where",
+    "while", "abstract", "become", "box", "do", "final", "macro", "override", "priv", "typeof",
+    "unsized", "virtual", "yield",
+];
+
+/// Returns the edition that made `name` a keyword, if it's one
+fn keyword_since(name: &str) -> Option<Edition> {
+    match name {
+        "async" | "await" | "dyn" | "try" => Some(Edition::E2018),
+        "gen" => Some(Edition::E2024),
+        name if KEYWORDS.contains(&name) => Some(Edition::E2015),
+        _ => None,
+    }
+}
+
+/// Make sure that `name` isn't a keyword in `edition`, since it couldn't be used as a module (or
+/// function) name. Newer editions reserve more keywords, like `async` (2018) and `gen` (2024)
+pub fn check_keyword(name: &str, edition: Edition) -> Result<()> {
+    match keyword_since(name) {
+        Some(since) if edition >= since => Error::other(format!(
+            "`{name}` is a keyword in the {} edition, so it can't be used as a name",
+            edition_str(edition)?
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Validate a package name. Package names can only have ASCII alphanumeric characters, `-` and
+/// `_` and must start with an alphabetic character or `_`
+pub fn validate_package_name(name: &str) -> Result<()> {
+    let valid = name
+        .bytes()
+        .next()
+        .is_some_and(|b| b.is_ascii_alphabetic() || b == b'_')
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if valid {
+        Ok(())
+    } else {
+        Error::other(format!("bad package name `{name}`"))
+    }
+}
+
+#[test]
+fn check_keyword_test() {
+    assert!(check_keyword("fn", Edition::E2015).is_err());
+    assert!(check_keyword("async", Edition::E2015).is_ok());
+    assert!(check_keyword("async", Edition::E2018).is_err());
+    assert!(check_keyword("try", Edition::E2021).is_err());
+    assert!(check_keyword("gen", Edition::E2021).is_ok());
+    assert!(check_keyword("gen", Edition::E2024).is_err());
+    assert!(check_keyword("engine", Edition::E2024).is_ok());
+}
@@ -15,31 +15,1299 @@
 */
 
 use {
-    crate::{module, module::ModuleOptions, utils, Error, Result},
-    cargo_toml::Workspace,
-    std::env,
+    crate::{
+        manifest,
+        module::{self, CreatedModule, ModuleOptions},
+        package,
+        root::{self, CARGO_TOML},
+        target::{self, CrateTarget},
+        transaction::Transaction,
+        utils, Error, Result,
+    },
+    cargo_toml::{Manifest, Package, Workspace},
+    std::{
+        collections::BTreeMap,
+        env, fs,
+        io::{self, IsTerminal, Write},
+        iter,
+        ops::Range,
+        path::{Component, Path, PathBuf},
+    },
+    toml_edit::{DocumentMut, Item, TableLike},
 };
 
+/// Create a module in a virtual workspace. The package is picked from the module path
+/// (`member::path`), then from the directory the user is in (`cwd`) and finally from the default
+/// members
 pub fn create_module_in_workspace(
     path: &str,
     options: ModuleOptions,
     workspace: Workspace,
-) -> Result<()> {
+    cwd: &Path,
+) -> Result<CreatedModule> {
     let path_segments: Vec<&str> = path.split("::").collect();
-    if path_segments.iter().any(|s| s.is_empty()) || path_segments.len() < 2 {
+    if path_segments.iter().any(|s| s.is_empty()) {
         return Error::other("Bad module path");
     }
-    let target_member = path_segments[0];
-    if workspace.members.contains(&target_member.to_owned()) {
+    let members = Members::load(&env::current_dir()?, &workspace)?;
+    if let Some(member) = members.qualifier(&path_segments, None) {
+        return create_module_in_member(&members, member, unqualified(path), options, cwd);
+    }
+    match members.containing(cwd).or(members.default_member()) {
+        Some(member) => create_module_in_member(&members, member, path, options, cwd),
+        None => Error::other(format!(
+            "can't tell which package `{path}` goes in. Use `--package <PACKAGE>` or \
+            `<PACKAGE>::{path}` (available packages: {})",
+            members.available()
+        )),
+    }
+}
+
+/// Create the module at `path` in the workspace member `member`. Relative paths are resolved
+/// against `cwd` if it's in the member
+pub fn create_module_in_member(
+    members: &Members,
+    member: &str,
+    path: &str,
+    options: ModuleOptions,
+    cwd: &Path,
+) -> Result<CreatedModule> {
+    let rel_dir = cwd
+        .strip_prefix(members.dir(member)?)
+        .unwrap_or_else(|_| Path::new(""));
+    members.in_member(member, |package| {
+        package::create_module_in_package(path, options, package, rel_dir)
+    })
+}
+
+/// Returns the module path without its package qualifier
+pub fn unqualified(path: &str) -> &str {
+    path.split_once("::").map_or(path, |(_, path)| path)
+}
+
+/// The members of a workspace, indexed by their package name. `members` and `exclude` are
+/// interpreted by [`expand_members`] and [`is_excluded`] (also used to find the workspace of a
+/// package) and `default-members` is interpreted here
+pub struct Members {
+    /// package name -> (member directory, package)
+    packages: BTreeMap<String, (PathBuf, Package)>,
+    /// the package names of the default members
+    default: Vec<String>,
+}
+
+impl Members {
+    /// Read the manifest of every member of `workspace`, whose root is at `root` (an absolute
+    /// path). Member globs are expanded like `cargo` does it
+    pub fn load(root: &Path, workspace: &Workspace) -> Result<Self> {
+        let mut packages: BTreeMap<String, (PathBuf, Package)> = BTreeMap::new();
+        for dir in expand_members(root, &workspace.members, &workspace.exclude)? {
+            let manifest = Manifest::from_path(dir.join(CARGO_TOML)).map_err(|e| {
+                Error::Other(format!(
+                    "failed to read workspace member `{}`: {e}",
+                    dir.display()
+                ))
+            })?;
+            let package = manifest.package.ok_or_else(|| {
+                Error::Other(format!(
+                    "workspace member `{}` isn't a package",
+                    dir.display()
+                ))
+            })?;
+            if let Some((other, _)) = packages.get(&package.name) {
+                return Error::other(format!(
+                    "two workspace members are named `{}`: `{}` and `{}`",
+                    package.name,
+                    other.display(),
+                    dir.display()
+                ));
+            }
+            packages.insert(package.name.clone(), (dir, package));
+        }
+        // like `cargo`, a root package is always a member
+        if !packages.values().any(|(dir, _)| dir == root) {
+            if let Some(package) = Manifest::from_path(root.join(CARGO_TOML))?.package {
+                packages
+                    .entry(package.name.clone())
+                    .or_insert_with(|| (root.to_owned(), package));
+            }
+        }
+        let mut default = Vec::new();
+        for dir in expand_members(root, &workspace.default_members, &[])? {
+            match packages.iter().find(|(_, (member, _))| *member == dir) {
+                Some((name, _)) => default.push(name.clone()),
+                None => {
+                    return Error::other(format!(
+                        "default member `{}` isn't a workspace member",
+                        dir.display()
+                    ))
+                }
+            }
+        }
+        Ok(Self { packages, default })
+    }
+    /// Returns true if `name` is a member
+    pub fn contains(&self, name: &str) -> bool {
+        self.packages.contains_key(name)
+    }
+    /// Returns the member that the module path is qualified with (`member::path`), if any. The
+    /// root package (for a root package that's also a workspace) doesn't qualify paths
+    pub fn qualifier<'a>(
+        &self,
+        path_segments: &[&'a str],
+        root_package: Option<&str>,
+    ) -> Option<&'a str> {
+        match path_segments {
+            [member, _, ..] if self.contains(member) && Some(*member) != root_package => {
+                Some(member)
+            }
+            _ => None,
+        }
+    }
+    /// Returns the default member, if there's exactly one
+    pub fn default_member(&self) -> Option<&str> {
+        match self.default.as_slice() {
+            [default] => Some(default),
+            _ => None,
+        }
+    }
+    /// Returns the member whose directory has `dir`, if any. Nested members win over the members
+    /// (like a root package) that they're in
+    pub fn containing(&self, dir: &Path) -> Option<&str> {
+        self.iter()
+            .filter(|(_, member)| dir.starts_with(member))
+            .max_by_key(|(_, member)| member.components().count())
+            .map(|(name, _)| name)
+    }
+    /// Returns the list of members, for error messages
+    pub fn available(&self) -> String {
+        let names: Vec<String> = self.names().map(|name| format!("`{name}`")).collect();
+        names.join(", ")
+    }
+    /// Returns the package names of the members
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.packages.keys().map(String::as_str)
+    }
+    /// Returns the package names of the members along with their directories
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Path)> {
+        self.packages
+            .iter()
+            .map(|(name, (dir, _))| (name.as_str(), dir.as_path()))
+    }
+    /// Returns the directory of the member `name`
+    pub fn dir(&self, name: &str) -> Result<&Path> {
+        self.get(name).map(|(dir, _)| dir.as_path())
+    }
+    /// Run `f` with the package `name` from within its member directory, switching back to the
+    /// current directory once done (even if `f` fails)
+    pub fn in_member<T>(&self, name: &str, f: impl FnOnce(Package) -> Result<T>) -> Result<T> {
+        let (dir, package) = self.get(name)?;
         // good, now switch to the package directory
         let cd = env::current_dir()?;
-        env::set_current_dir(target_member)?;
-        // now create the module
-        module::create_module(utils::get_root_file_name()?, &path_segments[1..], options)?;
+        env::set_current_dir(dir)?;
+        let ret = f(package.clone());
         env::set_current_dir(cd)?;
+        ret
+    }
+    fn get(&self, name: &str) -> Result<&(PathBuf, Package)> {
+        self.packages.get(name).ok_or_else(|| {
+            Error::Other(format!(
+                "package `{name}` not present in workspace (available packages: {}). consider \
+                creating it with `dab new-member`",
+                self.available()
+            ))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The kind of package that `new-member` creates
+pub enum MemberKind {
+    /// a binary (the default)
+    Bin,
+    /// a library (`--lib`)
+    Lib,
+    /// a procedural macro library (`proc-macro`)
+    ProcMacro,
+}
+
+/// Create a new package at `path` (relative to the workspace root, which has to be the current
+/// directory) and add it to the workspace members, unless a member glob already covers it. Any
+/// field in `[workspace.package]` is inherited by the new package
+pub fn new_member(path: &str, kind: MemberKind, workspace: &Workspace) -> Result<()> {
+    let root = env::current_dir()?;
+    let mut manifests = Manifests::default();
+    manifests.load_dir(&root)?;
+    let mut transaction = Transaction::default();
+    let (name, _) = stage_member(
+        &mut manifests,
+        &mut transaction,
+        &root,
+        path,
+        kind,
+        workspace,
+    )?;
+    manifests.stage(&mut transaction);
+    transaction.commit()?;
+    println!("Created package `{name}` at `{path}`");
+    Ok(())
+}
+
+/// Stage the creation of the package at `path` in `transaction`, along with its workspace member
+/// entry in the loaded `manifests`. Returns the name and the directory of the package
+fn stage_member(
+    manifests: &mut Manifests,
+    transaction: &mut Transaction,
+    root: &Path,
+    path: &str,
+    kind: MemberKind,
+    workspace: &Workspace,
+) -> Result<(String, PathBuf)> {
+    let dir = normalize(&root.join(path));
+    if !dir.starts_with(root) || dir == root {
+        return Error::other(format!("`{path}` isn't inside the workspace"));
+    }
+    if dir.exists() {
+        return Error::other(format!("`{path}` already exists"));
+    }
+    if is_excluded(root, &workspace.members, &workspace.exclude, &dir) {
+        return Error::other(format!("`{path}` is excluded from the workspace"));
+    }
+    // `cargo` rejects a directory matched by a member glob that isn't a package, so the new
+    // package can't be nested in a new directory like that (`crates/group` for `crates/*`)
+    for parent in dir
+        .ancestors()
+        .skip(1)
+        .take_while(|parent| *parent != root && !parent.exists())
+    {
+        if let Some(member) = covering_member(root, workspace, parent) {
+            return Error::other(format!(
+                "`{}` would be matched by the member `{member}` without being a package",
+                relative_path(root, parent)
+            ));
+        }
+    }
+    let name = dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| Error::Other(format!("bad package path `{path}`")))?;
+    utils::validate_package_name(name)?;
+    if Members::load(root, workspace)?.contains(name) {
+        return Error::other(format!(
+            "the workspace already has a package named `{name}`"
+        ));
+    }
+    // find the fields we can inherit
+    let root_doc = manifests.get_mut(root)?;
+    let inherited: Vec<&str> = root_doc
+        .get("workspace")
+        .and_then(|workspace| workspace.get("package"))
+        .and_then(|package| package.as_table_like())
+        .map(|package| package.iter().map(|(key, _)| key).collect())
+        .unwrap_or_default();
+
+    let package_manifest = match kind {
+        MemberKind::ProcMacro => {
+            let dependencies: Vec<&str> = root_doc
+                .get("workspace")
+                .and_then(|workspace| workspace.get("dependencies"))
+                .and_then(|dependencies| dependencies.as_table_like())
+                .map(|dependencies| dependencies.iter().map(|(key, _)| key).collect())
+                .unwrap_or_default();
+            proc_macro_manifest(name, &inherited, &dependencies)
+        }
+        _ => package_manifest(name, &inherited),
+    };
+
+    // create the package
+    let (root_file, root_file_contents) = match kind {
+        MemberKind::Bin => (utils::MAIN_RS, utils::MAIN_TEMPLATE),
+        MemberKind::Lib => (utils::LIB_RS, ""),
+        MemberKind::ProcMacro => (utils::LIB_RS, PROC_MACRO_TEMPLATE),
+    };
+    transaction.create(dir.join(CARGO_TOML), package_manifest);
+    transaction.create(dir.join(root_file), root_file_contents.to_owned());
+    // add it to the workspace
+    if !is_member_covered(root, workspace, &dir) {
+        let workspace = manifest::table_mut(root_doc.as_table_mut(), "workspace")?;
+        let members = workspace
+            .entry("members")
+            .or_insert_with(|| toml_edit::value(toml_edit::Array::new()))
+            .as_array_mut()
+            .ok_or_else(|| Error::Other("`workspace.members` isn't an array".into()))?;
+        manifest::push_formatted(members, relative_path(root, &dir).as_str());
+    }
+    Ok((name.to_owned(), dir))
+}
+
+/// Make the member `from` depend on the member `to` through a path dependency. If the workspace
+/// has a `[workspace.dependencies]` table, `to` is registered there (unless it already is) and
+/// `from` inherits it instead
+pub fn add_dependency(from: &str, to: &str, workspace: &Workspace) -> Result<()> {
+    let root = env::current_dir()?;
+    let members = Members::load(&root, workspace)?;
+    let (from_dir, to_dir) = (members.dir(from)?, members.dir(to)?);
+    if from == to {
+        return Error::other(format!("`{from}` can't depend on itself"));
+    }
+    let mut manifests = Manifests::default();
+    manifests.load_dir(&root)?;
+    manifests.load_dir(from_dir)?;
+    stage_dependency(&mut manifests, &root, (from, from_dir), (to, to_dir))?;
+    // the workspace and the member manifests are written together
+    let mut transaction = Transaction::default();
+    manifests.stage(&mut transaction);
+    transaction.commit()?;
+    println!("Added `{to}` as a dependency of `{from}`");
+    Ok(())
+}
+
+/// Make the package `from` depend on the member `to` (both with their directories) in the loaded
+/// `manifests`, registering `to` in `[workspace.dependencies]` if the workspace has that table
+fn stage_dependency(
+    manifests: &mut Manifests,
+    root: &Path,
+    (from, from_dir): (&str, &Path),
+    (to, to_dir): (&str, &Path),
+) -> Result<()> {
+    if manifests
+        .get_mut(from_dir)?
+        .get("dependencies")
+        .and_then(|dependencies| dependencies.get(to))
+        .is_some()
+    {
+        return Error::other(format!("`{from}` already depends on `{to}`"));
+    }
+    let root_doc = manifests.get_mut(root)?;
+    let dependency = match workspace_dependencies_mut(root_doc) {
+        Some(dependencies) => {
+            if dependencies.get(to).is_none() {
+                let path = relative_path(root, to_dir);
+                dependencies.insert(to, toml_edit::value(inline_table("path", path)));
+            }
+            inline_table("workspace", true)
+        }
+        None => inline_table("path", relative_path(from_dir, to_dir)),
+    };
+    manifest::table_mut(manifests.get_mut(from_dir)?.as_table_mut(), "dependencies")?
+        .insert(to, toml_edit::value(dependency));
+    Ok(())
+}
+
+/// Create the proc-macro package `path` for the member `for_package`, which then depends on it.
+/// A plain name (like `skyd-derive`) puts the package next to `for_package`, while anything else
+/// is a path relative to the workspace root. With `--reexport`, the macros are re-exported from
+/// the library of `for_package`
+pub fn new_proc_macro(
+    path: &str,
+    for_package: &str,
+    options: &ModuleOptions,
+    workspace: &Workspace,
+) -> Result<()> {
+    let root = env::current_dir()?;
+    let members = Members::load(&root, workspace)?;
+    let for_dir = members.dir(for_package)?;
+    let path = if path.contains(['/', '\\']) || for_dir == root {
+        path.to_owned()
+    } else {
+        relative_path(&root, &for_dir.parent().unwrap_or(&root).join(path))
+    };
+    // the package, the dependency on it and the re-export are made together
+    let mut manifests = Manifests::default();
+    manifests.load_dir(&root)?;
+    manifests.load_dir(for_dir)?;
+    let mut transaction = Transaction::default();
+    let (name, dir) = stage_member(
+        &mut manifests,
+        &mut transaction,
+        &root,
+        &path,
+        MemberKind::ProcMacro,
+        workspace,
+    )?;
+    stage_dependency(&mut manifests, &root, (for_package, for_dir), (&name, &dir))?;
+    if options.reexport {
+        let reexport = format!("pub use {}::*;", name.replace('-', "_"));
+        let (root_file, declared) = members.in_member(for_package, |package| {
+            let root_file = target::root_file(&package, Some(&CrateTarget::Lib))?;
+            let declared =
+                module::declare_use(&package, Path::new(&root_file), &reexport, options)?;
+            Ok((root_file, declared))
+        })?;
+        if let Some(contents) = declared {
+            transaction.write(for_dir.join(root_file), contents);
+        }
+    }
+    manifests.stage(&mut transaction);
+    transaction.commit()?;
+    println!("Created package `{name}` at `{path}`");
+    println!("Added `{name}` as a dependency of `{for_package}`");
+    if options.reexport {
+        println!("Re-exported `{name}` from `{for_package}`");
+    }
+    Ok(())
+}
+
+/// Returns `{ key = value }`
+fn inline_table(key: &str, value: impl Into<toml_edit::Value>) -> toml_edit::InlineTable {
+    let mut table = toml_edit::InlineTable::new();
+    table.insert(key, value.into());
+    table
+}
+
+/// Remove the member `name` from the workspace and delete its directory (after asking, unless
+/// `force` is set), along with the dependencies of the other members on it. Members whose code
+/// still uses it have to be changed first
+pub fn remove_member(name: &str, force: bool, workspace: &Workspace) -> Result<()> {
+    let root = env::current_dir()?;
+    let members = Members::load(&root, workspace)?;
+    let dir = members.dir(name)?.to_owned();
+    if dir == root {
+        return Error::other("the root package can't be removed");
+    }
+    let mut manifests = Manifests::load(&root, &members)?;
+    let mut dependents = Vec::new();
+    let mut users = Vec::new();
+    for (member, member_dir) in members.iter() {
+        let doc = manifests.get_mut(member_dir)?;
+        let mut keys = Vec::new();
+        for table in manifest::dependency_tables_mut(doc) {
+            for key in manifest::local_dependency_keys(&*table, name) {
+                table.remove(&key);
+                keys.push(key.replace('-', "_"));
+            }
+        }
+        if keys.is_empty() {
+            continue;
+        }
+        dependents.push(format!("`{member}`"));
+        for file in rust_files(member_dir)? {
+            let source = fs::read_to_string(&file)?;
+            if keys.iter().any(|key| !crate_paths(&source, key).is_empty()) {
+                users.push(format!("`{}`", relative_path(&root, &file)));
+            }
+        }
+    }
+    if !users.is_empty() {
+        return Error::other(format!("`{name}` is still used by {}", users.join(", ")));
+    }
+    let path = relative_path(&root, &dir);
+    if !force && !confirm(&format!("Delete `{path}` and everything in it?"))? {
+        return Error::other(format!(
+            "`{name}` wasn't removed (use `--force` to remove it without asking)"
+        ));
+    }
+    let root_doc = manifests.get_mut(&root)?;
+    for key in ["members", "default-members"] {
+        retarget_member_entries(root_doc, &root, key, &dir, None);
+    }
+    if let Some(dependencies) = workspace_dependencies_mut(root_doc) {
+        for key in manifest::local_dependency_keys(dependencies, name) {
+            dependencies.remove(&key);
+        }
+    }
+    let mut transaction = Transaction::default();
+    manifests.stage(&mut transaction);
+    transaction.remove_dir(&dir);
+    transaction.commit()?;
+    println!("Removed package `{name}`");
+    if !dependents.is_empty() {
+        println!(
+            "Removed the dependencies on it from {}",
+            dependents.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Ask the user to confirm `question`. Without a terminal to ask on, the answer is no
+fn confirm(question: &str) -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        return Ok(false);
+    }
+    print!("{question} [y/N] ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "yes"))
+}
+
+/// Rename the member `old` to `new`, along with its directory. The workspace manifest, the
+/// dependencies of the other members and the `old::` paths in the crates that depend on it are
+/// updated to match. Either all of it is done, or nothing is
+pub fn move_member(old: &str, new: &str, workspace: &Workspace) -> Result<()> {
+    let root = env::current_dir()?;
+    let members = Members::load(&root, workspace)?;
+    let old_dir = members.dir(old)?.to_owned();
+    if old_dir == root {
+        return Error::other("the root package can't be moved");
+    }
+    utils::validate_package_name(new)?;
+    if members.contains(new) {
+        return Error::other(format!("the workspace already has a package named `{new}`"));
+    }
+    let new_dir = old_dir.with_file_name(new);
+    let new_path = relative_path(&root, &new_dir);
+    if new_dir.exists() {
+        return Error::other(format!("`{new_path}` already exists"));
+    }
+    let mut manifests = Manifests::load(&root, &members)?;
+    // the workspace
+    let root_doc = manifests.get_mut(&root)?;
+    if !retarget_member_entries(root_doc, &root, "members", &old_dir, Some(&new_path))
+        && !is_member_covered(&root, workspace, &new_dir)
+    {
+        let workspace = manifest::table_mut(root_doc.as_table_mut(), "workspace")?;
+        let members = workspace
+            .entry("members")
+            .or_insert_with(|| toml_edit::value(toml_edit::Array::new()))
+            .as_array_mut()
+            .ok_or_else(|| Error::Other("`workspace.members` isn't an array".into()))?;
+        manifest::push_formatted(members, new_path.as_str());
+    }
+    retarget_member_entries(
+        root_doc,
+        &root,
+        "default-members",
+        &old_dir,
+        Some(&new_path),
+    );
+    if let Some(dependencies) = workspace_dependencies_mut(root_doc) {
+        retarget_dependencies(dependencies, &root, old, new, &new_dir);
+    }
+    // the package itself
+    let doc = manifests.get_mut(&old_dir)?;
+    manifest::set_str(manifest::table_mut(doc, "package")?, "name", new);
+    // the crate keeps its name if the library target is named explicitly
+    let crate_renamed = doc.get("lib").and_then(|lib| lib.get("name")).is_none();
+    // and the packages that depend on it
+    let mut dependents = Vec::new();
+    for (_, dir) in members.iter() {
+        let mut renamed = false;
+        for table in manifest::dependency_tables_mut(manifests.get_mut(dir)?) {
+            renamed |= retarget_dependencies(table, dir, old, new, &new_dir);
+        }
+        if renamed {
+            dependents.push(dir);
+        }
+    }
+    let mut transaction = Transaction::default();
+    if crate_renamed {
+        let (old_crate, new_crate) = (old.replace('-', "_"), new.replace('-', "_"));
+        for dir in dependents.into_iter().chain(iter::once(old_dir.as_path())) {
+            for file in rust_files(dir)? {
+                let source = fs::read_to_string(&file)?;
+                if let Some(source) = rename_crate_paths(&source, &old_crate, &new_crate) {
+                    transaction.write(file, source);
+                }
+            }
+        }
+    }
+    manifests.stage(&mut transaction);
+    transaction.rename(&old_dir, &new_dir);
+    transaction.commit()?;
+    println!("Renamed package `{old}` to `{new}` (now at `{new_path}`)");
+    Ok(())
+}
+
+/// The manifests of the workspace root and its members (by directory), edited in memory so that
+/// they can be written in one transaction
+#[derive(Default)]
+struct Manifests(BTreeMap<PathBuf, (String, DocumentMut)>);
+
+impl Manifests {
+    fn load(root: &Path, members: &Members) -> Result<Self> {
+        let mut manifests = Self::default();
+        for dir in members.iter().map(|(_, dir)| dir).chain(iter::once(root)) {
+            manifests.load_dir(dir)?;
+        }
+        Ok(manifests)
+    }
+    /// Load the manifest in `dir`, unless it's already loaded
+    fn load_dir(&mut self, dir: &Path) -> Result<()> {
+        if !self.0.contains_key(dir) {
+            let path = dir.join(CARGO_TOML);
+            let contents = fs::read_to_string(&path)?;
+            let doc = manifest::parse(&path, &contents)?;
+            self.0.insert(dir.to_owned(), (contents, doc));
+        }
         Ok(())
+    }
+    fn get_mut(&mut self, dir: &Path) -> Result<&mut DocumentMut> {
+        self.0
+            .get_mut(dir)
+            .map(|(_, doc)| doc)
+            .ok_or_else(|| Error::Other(format!("no manifest loaded for `{}`", dir.display())))
+    }
+    /// Add the manifests that changed to the transaction
+    fn stage(self, transaction: &mut Transaction) {
+        for (dir, (contents, doc)) in self.0 {
+            let edited = doc.to_string();
+            if edited != contents {
+                transaction.write(dir.join(CARGO_TOML), edited);
+            }
+        }
+    }
+}
+
+/// Returns `[workspace.dependencies]`, if there's one
+fn workspace_dependencies_mut(doc: &mut DocumentMut) -> Option<&mut dyn TableLike> {
+    doc.get_mut("workspace")
+        .and_then(|workspace| workspace.get_mut("dependencies"))
+        .and_then(Item::as_table_like_mut)
+}
+
+/// Replace the entries of the array `workspace.<key>` that point at `dir` with `to`, or remove
+/// them if `to` is `None`. Returns true if there were any
+fn retarget_member_entries(
+    doc: &mut DocumentMut,
+    root: &Path,
+    key: &str,
+    dir: &Path,
+    to: Option<&str>,
+) -> bool {
+    let Some(array) = doc
+        .get_mut("workspace")
+        .and_then(|workspace| workspace.get_mut(key))
+        .and_then(Item::as_array_mut)
+    else {
+        return false;
+    };
+    let found: Vec<usize> = array
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| {
+            entry
+                .as_str()
+                .is_some_and(|p| normalize(&root.join(p)) == dir)
+        })
+        .map(|(i, _)| i)
+        .collect();
+    for &i in found.iter().rev() {
+        match (to, array.get_mut(i)) {
+            (Some(to), Some(entry)) => manifest::replace_str(entry, to),
+            _ => {
+                let decor = array.remove(i).decor().clone();
+                // the next entry takes the place (and the formatting) of the removed one. The
+                // prefix might have a comment on the entry before it, which has to stay
+                if let Some(next) = array.get_mut(i) {
+                    *next.decor_mut() = decor;
+                } else if let Some((comment, _)) = decor
+                    .prefix()
+                    .and_then(|prefix| prefix.as_str())
+                    .and_then(|prefix| prefix.rsplit_once('\n'))
+                {
+                    // while a comment in the trailing space is on the removed entry
+                    let trailing = array.trailing().as_str().unwrap_or("");
+                    let trailing = trailing.find('\n').map_or(trailing, |lf| &trailing[lf..]);
+                    array.set_trailing(format!("{comment}{trailing}"));
+                }
+            }
+        }
+    }
+    !found.is_empty()
+}
+
+/// Point the dependencies in `table` (of the manifest in `dir`) on the member `old` to the same
+/// member, now called `new` at `new_dir`. Returns true if a dependency was renamed, i.e. if the
+/// code has to use the new name
+fn retarget_dependencies(
+    table: &mut dyn TableLike,
+    dir: &Path,
+    old: &str,
+    new: &str,
+    new_dir: &Path,
+) -> bool {
+    let mut renamed = false;
+    for key in manifest::local_dependency_keys(table, old) {
+        let Some(dependency) = table.get_mut(&key).and_then(Item::as_table_like_mut) else {
+            continue;
+        };
+        if dependency.contains_key("path") {
+            manifest::set_str(dependency, "path", &relative_path(dir, new_dir));
+        }
+        if key == old {
+            manifest::rename_key(table, old, new);
+            renamed = true;
+        } else {
+            // a renamed dependency (`alias = { package = "old" }`)
+            manifest::set_str(dependency, "package", new);
+        }
+    }
+    renamed
+}
+
+/// Returns the Rust files of the package in `dir`, leaving out `target` and nested packages
+fn rust_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if path.is_dir() {
+            if name != "target" && !name.starts_with('.') && !path.join(CARGO_TOML).exists() {
+                files.extend(rust_files(&path)?);
+            }
+        } else if name.ends_with(".rs") {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Rewrite the paths into the crate `old` (`old::x`, `::old::x`, `use old;` and
+/// `extern crate old`) to use `new` instead. Strings and comments are left alone. Returns `None`
+/// if there were none
+fn rename_crate_paths(source: &str, old: &str, new: &str) -> Option<String> {
+    let paths = crate_paths(source, old);
+    if paths.is_empty() {
+        return None;
+    }
+    let mut renamed = String::with_capacity(source.len());
+    let mut last = 0;
+    for at in paths {
+        renamed.push_str(&source[last..at]);
+        renamed.push_str(new);
+        last = at + old.len();
+    }
+    renamed.push_str(&source[last..]);
+    Some(renamed)
+}
+
+/// Returns the offsets of the paths into the crate `name` in `source` (see
+/// [`rename_crate_paths`]), leaving out strings and comments
+fn crate_paths(source: &str, name: &str) -> Vec<usize> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let skipped = strings_and_comments(source);
+    let mut paths = Vec::new();
+    for (at, _) in source.match_indices(name) {
+        if skipped.iter().any(|range| range.contains(&at)) {
+            continue;
+        }
+        let (before, after) = (&source[..at], &source[at + name.len()..]);
+        // `x::name` and `x.name::<T>()` aren't paths into the crate
+        let starts_path = match before.strip_suffix("::") {
+            Some(before) => !before.ends_with(is_ident),
+            None => !before.ends_with(|c: char| is_ident(c) || c == '.'),
+        };
+        let imported = ["use", "extern crate"].iter().any(|keyword| {
+            before
+                .trim_end()
+                .strip_suffix(keyword)
+                .is_some_and(|before| !before.ends_with(is_ident))
+        });
+        if starts_path && !after.starts_with(is_ident) && (after.starts_with("::") || imported) {
+            paths.push(at);
+        }
+    }
+    paths
+}
+
+/// Returns the byte ranges of the string and character literals and the comments in `source`
+fn strings_and_comments(source: &str) -> Vec<Range<usize>> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let mut ranges = Vec::new();
+    let mut at = 0;
+    while let Some(c) = source[at..].chars().next() {
+        let rest = &source[at..];
+        let end = if rest.starts_with("//") {
+            rest.find('\n').map_or(source.len(), |lf| at + lf)
+        } else if rest.starts_with("/*") {
+            // block comments nest
+            let (mut depth, mut i) = (0, 0);
+            loop {
+                match rest.get(i..i + 2) {
+                    Some("/*") => (depth, i) = (depth + 1, i + 2),
+                    Some("*/") if depth == 1 => break at + i + 2,
+                    Some("*/") => (depth, i) = (depth - 1, i + 2),
+                    Some(_) => i += rest[i..].chars().next().map_or(1, char::len_utf8),
+                    None => break source.len(),
+                }
+            }
+        } else if let Some((open, hashes)) =
+            raw_string_start(rest).filter(|_| !source[..at].ends_with(is_ident))
+        {
+            let close = format!("\"{}", "#".repeat(hashes));
+            rest[open..]
+                .find(&close)
+                .map_or(source.len(), |i| at + open + i + close.len())
+        } else if c == '"' {
+            let mut escaped = false;
+            rest.char_indices()
+                .skip(1)
+                .find(|&(_, c)| {
+                    let end = c == '"' && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    end
+                })
+                .map_or(source.len(), |(i, _)| at + i + 1)
+        } else if c == '\'' {
+            // a character literal, unless it's a lifetime (or a label)
+            let mut chars = rest.char_indices().skip(1);
+            match (chars.next(), chars.next()) {
+                (Some((_, '\\')), _) => rest[2..]
+                    .find('\'')
+                    .map_or(source.len(), |i| at + 2 + i + 1),
+                (Some(_), Some((i, '\''))) => at + i + 1,
+                _ => at,
+            }
+        } else {
+            at
+        };
+        if end > at {
+            ranges.push(at..end);
+            at = end;
+        } else {
+            at += c.len_utf8();
+        }
+    }
+    ranges
+}
+
+/// If `source` starts with a raw string (`r#"`), returns the offset of its contents and the
+/// number of `#`s that close it
+fn raw_string_start(source: &str) -> Option<(usize, usize)> {
+    let raw = source
+        .strip_prefix("br")
+        .or_else(|| source.strip_prefix('r'))?;
+    let hashes = raw.len() - raw.trim_start_matches('#').len();
+    raw[hashes..]
+        .starts_with('"')
+        .then(|| (source.len() - raw.len() + hashes + 1, hashes))
+}
+
+/// Returns the manifest for a new package called `name`, inheriting the `inherited` fields from
+/// the workspace
+fn package_manifest(name: &str, inherited: &[&str]) -> String {
+    let mut manifest = format!("[package]\nname = \"{name}\"\n");
+    for (field, default) in [("version", "0.1.0"), ("edition", "2024")] {
+        if inherited.contains(&field) {
+            manifest.push_str(&format!("{field}.workspace = true\n"));
+        } else {
+            manifest.push_str(&format!("{field} = \"{default}\"\n"));
+        }
+    }
+    for field in inherited {
+        if !matches!(*field, "version" | "edition") {
+            manifest.push_str(&format!("{field}.workspace = true\n"));
+        }
+    }
+    manifest.push_str("\n[dependencies]\n");
+    manifest
+}
+
+/// The dependencies of a new proc-macro package (name and version)
+const PROC_MACRO_DEPENDENCIES: [(&str, &str); 2] = [("quote", "1"), ("syn", "2")];
+
+/// The root file of a new proc-macro package
+const PROC_MACRO_TEMPLATE: &str = "\
+use {
+    proc_macro::TokenStream,
+    quote::quote,
+    syn::{parse_macro_input, DeriveInput},
+};
+
+/// A sample derive that adds a `type_name` function to the type
+#[proc_macro_derive(TypeName)]
+pub fn derive_type_name(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    quote! {
+        impl #impl_generics #name #ty_generics #where_clause {
+            pub fn type_name() -> &'static str {
+                stringify!(#name)
+            }
+        }
+    }
+    .into()
+}
+";
+
+/// Returns the manifest for a new proc-macro package called `name`. Its dependencies are
+/// inherited if they're in `workspace_dependencies`
+fn proc_macro_manifest(name: &str, inherited: &[&str], workspace_dependencies: &[&str]) -> String {
+    let manifest = package_manifest(name, inherited);
+    let mut manifest = manifest
+        .strip_suffix("[dependencies]\n")
+        .unwrap_or(&manifest)
+        .to_owned();
+    manifest.push_str("[lib]\nproc-macro = true\n\n[dependencies]\n");
+    for (dependency, version) in PROC_MACRO_DEPENDENCIES {
+        if workspace_dependencies.contains(&dependency) {
+            manifest.push_str(&format!("{dependency}.workspace = true\n"));
+        } else {
+            manifest.push_str(&format!("{dependency} = \"{version}\"\n"));
+        }
+    }
+    manifest
+}
+
+/// Returns the path to `to` relative to `from` (both absolute and normalized) as it would be
+/// written in a manifest
+fn relative_path(from: &Path, to: &Path) -> String {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let path: Vec<_> = from[common..]
+        .iter()
+        .map(|_| "..".into())
+        .chain(to[common..].iter().map(|c| c.as_os_str().to_string_lossy()))
+        .collect();
+    if path.is_empty() {
+        ".".to_owned()
     } else {
-        // TODO(@ohsayan): Enable package creation in workspace
-        Error::other("package not present in workspace `Cargo.toml`. consider adding it there")
+        path.join("/")
+    }
+}
+
+/// Returns true if `dir` is already a member through the `members` of the workspace
+fn is_member_covered(root: &Path, workspace: &Workspace, dir: &Path) -> bool {
+    covering_member(root, workspace, dir).is_some()
+}
+
+/// Returns the entry of `workspace.members` (a path or a glob) that covers `dir`, if any
+fn covering_member<'a>(root: &Path, workspace: &'a Workspace, dir: &Path) -> Option<&'a str> {
+    workspace
+        .members
+        .iter()
+        .map(String::as_str)
+        .find(|member| matches_member(root, member, dir))
+}
+
+/// Returns true if the member entry `member` (a path or a glob, relative to `root`) matches
+/// `dir`, whether `dir` exists or not
+fn matches_member(root: &Path, member: &str, dir: &Path) -> bool {
+    let member = normalize(&root.join(member));
+    // like `cargo`, `*` doesn't match across directories (`crates/*` isn't `crates/a/b`)
+    let options = glob::MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+    member == dir
+        || glob::Pattern::new(&member.to_string_lossy())
+            .is_ok_and(|pattern| pattern.matches_path_with(dir, options))
+}
+
+/// Returns true if the workspace at `root` excludes `dir`, that is if `dir` is under one of the
+/// `exclude` paths without being under an explicit (non-glob) entry of `members`
+pub fn is_excluded(root: &Path, members: &[String], exclude: &[String], dir: &Path) -> bool {
+    let dir = normalize(dir);
+    let under = |path: &String| dir.starts_with(normalize(&root.join(path)));
+    exclude.iter().any(under) && !members.iter().filter(|member| !is_glob(member)).any(under)
+}
+
+/// Returns true if the member entry is a glob
+fn is_glob(member: &str) -> bool {
+    member.contains(['*', '?', '['])
+}
+
+/// Expand the member paths (which can be globs) relative to `root`. Like `cargo`, directories
+/// matched by a glob are left out if they're excluded (see [`is_excluded`]), don't have a
+/// `Cargo.toml` or are the root of another workspace, while explicit members are always kept
+pub fn expand_members(root: &Path, members: &[String], exclude: &[String]) -> Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    for member in members {
+        let pattern = root.join(member);
+        let matched: Vec<PathBuf> = if is_glob(member) {
+            glob::glob(&pattern.to_string_lossy())
+                .map_err(|e| Error::Other(format!("bad member glob `{member}`: {e}")))?
+                .filter_map(|path| path.ok())
+                .map(|path| normalize(&path))
+                // the walk and `covering_member` have to agree on what the glob matches
+                .filter(|path| matches_member(root, member, path))
+                .filter(|path| path.join(CARGO_TOML).is_file())
+                .filter(|path| path == root || !is_workspace_root(path))
+                .filter(|path| !is_excluded(root, members, exclude, path))
+                .collect()
+        } else {
+            let dir = normalize(&pattern);
+            if dir != root && is_workspace_root(&dir) {
+                return Error::other(format!(
+                    "member `{member}` is the root of another workspace"
+                ));
+            }
+            vec![dir]
+        };
+        for dir in matched {
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
     }
+    Ok(dirs)
+}
+
+/// Returns true if `dir` has a workspace manifest
+fn is_workspace_root(dir: &Path) -> bool {
+    let manifest = dir.join(CARGO_TOML);
+    manifest.is_file()
+        && root::read_raw_manifest(&manifest)
+            .is_ok_and(|manifest| manifest.get("workspace").is_some())
+}
+
+/// Normalize `.` and `..` in the path without touching the file system
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[test]
+fn is_member_covered_test() {
+    let workspace: Workspace = toml::from_str(r#"members = ["crates/*", "tools"]"#).unwrap();
+    let root = Path::new("/ws");
+    assert!(is_member_covered(
+        root,
+        &workspace,
+        Path::new("/ws/crates/skyd")
+    ));
+    assert!(is_member_covered(root, &workspace, Path::new("/ws/tools")));
+    assert!(!is_member_covered(
+        root,
+        &workspace,
+        Path::new("/ws/crates/group/skyd")
+    ));
+    assert!(!is_member_covered(
+        root,
+        &workspace,
+        Path::new("/ws/tools/gen")
+    ));
+    assert_eq!(
+        covering_member(root, &workspace, Path::new("/ws/crates/group")),
+        Some("crates/*")
+    );
+}
+
+#[test]
+fn is_excluded_test() {
+    let root = Path::new("/ws");
+    let members = ["crates/*".to_owned(), "crates/gen".to_owned()];
+    let exclude = ["crates".to_owned()];
+    assert!(is_excluded(
+        root,
+        &members,
+        &exclude,
+        Path::new("/ws/crates/skyd")
+    ));
+    assert!(!is_excluded(
+        root,
+        &members,
+        &exclude,
+        Path::new("/ws/crates/gen")
+    ));
+    assert!(!is_excluded(
+        root,
+        &members,
+        &exclude,
+        Path::new("/ws/tools")
+    ));
+    assert!(!is_excluded(
+        root,
+        &members,
+        &[],
+        Path::new("/ws/crates/skyd")
+    ));
+}
+
+#[test]
+fn expand_members_test() {
+    let root = env::current_dir().unwrap();
+    // the only `Cargo.toml` around is our own
+    let members = expand_members(&root, &["*".to_owned(), ".".to_owned()], &[]).unwrap();
+    assert_eq!(members.len(), 1);
+    assert_eq!(members[0], root);
+    let members = expand_members(&root, &["./src/../".to_owned()], &["src".to_owned()]).unwrap();
+    assert_eq!(members.len(), 1);
+    assert_eq!(members[0], root);
+    // exclusions only apply to globs
+    let members = expand_members(&root, &["[s]rc/..".to_owned()], &["src".to_owned()]).unwrap();
+    assert_eq!(members.len(), 1);
+    let members = expand_members(&root, &["[s]rc/..".to_owned()], &[".".to_owned()]).unwrap();
+    assert!(members.is_empty());
+    let members = expand_members(&root, &["src/x".to_owned()], &["src".to_owned()]).unwrap();
+    assert_eq!(members.len(), 1);
+}
+
+#[test]
+fn containing_test() {
+    let members = Members {
+        packages: [("skyd", "/ws"), ("libsky", "/ws/libsky")]
+            .into_iter()
+            .map(|(name, dir)| (name.to_owned(), (dir.into(), Package::new(name, "0.1.0"))))
+            .collect(),
+        default: Vec::new(),
+    };
+    assert_eq!(
+        members.containing(Path::new("/ws/libsky/src")),
+        Some("libsky")
+    );
+    assert_eq!(members.containing(Path::new("/ws/src")), Some("skyd"));
+    assert_eq!(members.containing(Path::new("/elsewhere")), None);
+    assert_eq!(members.available(), "`libsky`, `skyd`");
+}
+
+#[test]
+fn relative_path_test() {
+    let root = Path::new("/ws");
+    assert_eq!(
+        relative_path(root, Path::new("/ws/crates/skyd")),
+        "crates/skyd"
+    );
+    assert_eq!(
+        relative_path(Path::new("/ws/skyd"), Path::new("/ws/libsky")),
+        "../libsky"
+    );
+    assert_eq!(
+        relative_path(Path::new("/ws/server/skyd"), Path::new("/ws/libsky")),
+        "../../libsky"
+    );
+    assert_eq!(relative_path(root, Path::new("/ws/libsky")), "libsky");
+    assert_eq!(relative_path(Path::new("/ws/skyd"), root), "..");
+    assert_eq!(relative_path(root, root), ".");
+}
+
+#[test]
+fn rename_crate_paths_test() {
+    const SOURCE: &str = r##"use libsky::{Query, Response};
+pub use libsky;
+extern crate libsky as sky;
+use crate::libsky::Local;
+
+fn main() {
+    let libsky = ::libsky::connect();
+    let r = libsky.call::<Response>(libsky::VERSION);
+    let libsky_ext = my_libsky::x();
+    // libsky::VERSION is printed below
+    println!("libsky::VERSION = {}", libsky::VERSION);
+    let raw = r#"use libsky;"#;
+    let c = '"';
+    let lifetime: &'static str = libsky::NAME;
+}
+"##;
+    assert_eq!(
+        rename_crate_paths(SOURCE, "libsky", "skylib").unwrap(),
+        r##"use skylib::{Query, Response};
+pub use skylib;
+extern crate skylib as sky;
+use crate::libsky::Local;
+
+fn main() {
+    let libsky = ::skylib::connect();
+    let r = libsky.call::<Response>(skylib::VERSION);
+    let libsky_ext = my_libsky::x();
+    // libsky::VERSION is printed below
+    println!("libsky::VERSION = {}", skylib::VERSION);
+    let raw = r#"use libsky;"#;
+    let c = '"';
+    let lifetime: &'static str = skylib::NAME;
+}
+"##
+    );
+    assert!(rename_crate_paths("let libsky = 1;", "libsky", "skylib").is_none());
+    assert!(rename_crate_paths(
+        "/* libsky::a /* */ libsky::b */ let s = b\"libsky::c\\\"libsky::d\";",
+        "libsky",
+        "skylib"
+    )
+    .is_none());
+}
+
+#[test]
+fn retarget_member_entries_test() {
+    const MANIFEST: &str = r#"[workspace]
+members = [
+    "skyd",
+    "./libsky", # the library
+    "tools", # our tools
+]
+default-members = ["tools", "skyd"]
+"#;
+    let root = Path::new("/ws");
+    let mut doc: DocumentMut = MANIFEST.parse().unwrap();
+    let skylib = Some("skylib");
+    assert!(retarget_member_entries(
+        &mut doc,
+        root,
+        "members",
+        &root.join("libsky"),
+        skylib
+    ));
+    assert!(retarget_member_entries(
+        &mut doc,
+        root,
+        "members",
+        &root.join("tools"),
+        None
+    ));
+    assert!(retarget_member_entries(
+        &mut doc,
+        root,
+        "default-members",
+        &root.join("tools"),
+        None
+    ));
+    assert!(!retarget_member_entries(
+        &mut doc,
+        root,
+        "members",
+        &root.join("x"),
+        None
+    ));
+    assert_eq!(
+        doc.to_string(),
+        r#"[workspace]
+members = [
+    "skyd",
+    "skylib", # the library
+]
+default-members = ["skyd"]
+"#
+    );
+}
+
+#[test]
+fn package_manifest_test() {
+    assert_eq!(
+        package_manifest("skyhash", &[]),
+        "[package]\nname = \"skyhash\"\nversion = \"0.1.0\"\nedition = \"2024\"\n\n[dependencies]\n"
+    );
+    assert_eq!(
+        package_manifest("skyhash", &["license", "version", "edition"]),
+        "\
+[package]
+name = \"skyhash\"
+version.workspace = true
+edition.workspace = true
+license.workspace = true
+
+[dependencies]
+"
+    );
+}
+
+#[test]
+fn proc_macro_manifest_test() {
+    assert_eq!(
+        proc_macro_manifest("skyd-derive", &["edition"], &["syn"]),
+        "\
+[package]
+name = \"skyd-derive\"
+version = \"0.1.0\"
+edition.workspace = true
+
+[lib]
+proc-macro = true
+
+[dependencies]
+quote = \"1\"
+syn.workspace = true
+"
+    );
 }